@@ -0,0 +1,15 @@
+//! Checks that `to_markup` is the exact inverse of `parse`, and that neither
+//! parsing nor rendering panics, for arbitrary input.
+//!
+//! Run with `cargo fuzz run round_trip` from the repository root, see the
+//! README for running it with the seed corpus in `fuzz/seeds/round_trip`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+  let parts = sillycode::parse(input);
+  assert_eq!(sillycode::to_markup(&parts), input);
+  sillycode::render(parts, false);
+});
@@ -86,6 +86,87 @@ test('parse: a bunch of fake tags', () => {
   ]);
 });
 
+test('parse: link with href', () => {
+  expect(parse('[url=https://example.com]click [b]here[/b][/url]')).toEqual([
+    { type: 'link', href: 'https://example.com' },
+    { type: 'text', text: 'click ' },
+    { type: 'style', style: StyleKind.BOLD, enable: true },
+    { type: 'text', text: 'here' },
+    { type: 'style', style: StyleKind.BOLD, enable: false },
+    { type: 'style', style: StyleKind.LINK, enable: false }
+  ]);
+  expect(parse('[url=]')).toEqual([
+    { type: 'text', text: '[url=]' }
+  ]);
+  // the limit is in characters, not utf-16 code units
+  expect(parse('[url=' + '😀'.repeat(2044) + ']')).toEqual([
+    { type: 'link', href: '😀'.repeat(2044) }
+  ]);
+  expect(parse('[url=' + '😀'.repeat(2045) + ']')[0]!.type).toBe('text');
+});
+
+test('parse: code is verbatim', () => {
+  expect(parse('a [code][b]x\\[:)][/code] [b]')).toEqual([
+    { type: 'text', text: 'a ' },
+    { type: 'code', code: '[b]x\\[:)]' },
+    { type: 'text', text: ' ' },
+    { type: 'style', style: StyleKind.BOLD, enable: true }
+  ]);
+  expect(parse('[code]unclosed')).toEqual([
+    { type: 'text', text: '[code]unclosed' }
+  ]);
+  expect(parse('[code]a\nb[/code]')).toEqual([
+    { type: 'text', text: '[code]a' },
+    { type: 'newline' },
+    { type: 'text', text: 'b[/code]' }
+  ]);
+  expect(parse('[code]a\n[code]b[/code] [code]c[/code]')).toEqual([
+    { type: 'text', text: '[code]a' },
+    { type: 'newline' },
+    { type: 'code', code: 'b' },
+    { type: 'text', text: ' ' },
+    { type: 'code', code: 'c' }
+  ]);
+});
+
+test('parse: unclosed code is linear', () => {
+  // every opener used to scan the rest of the input for its closing tag
+  expect(parse('[code]'.repeat(100000) + '\n' + 'x'.repeat(1000000) + '[/code]').length).toBe(3);
+  expect(parse('[code]'.repeat(100000) + 'x'.repeat(1000000)).length).toBe(1);
+});
+
+test('parse: code block', () => {
+  expect(parse('[codeblock]\n  [b]x[/b] \\\n[/code][/codeblock]\n')).toEqual([
+    { type: 'codeblock', code: '\n  [b]x[/b] \\\n[/code]' },
+    { type: 'newline' }
+  ]);
+});
+
+test('parse: unclosed code block is linear', () => {
+  expect(parse('[codeblock]'.repeat(100000) + 'x'.repeat(1000000)).length).toBe(1);
+  expect(parse('[codeblock]\n'.repeat(100000) + '[codeblock]a[/codeblock]')).toEqual([
+    { type: 'codeblock', code: '\n' + '[codeblock]\n'.repeat(99999) + '[codeblock]a' }
+  ]);
+});
+
+test('parse: quotes', () => {
+  expect(parse('[quote=lua]hi[/quote][quote=]')).toEqual([
+    { type: 'quote', enable: true, author: 'lua' },
+    { type: 'text', text: 'hi' },
+    { type: 'quote', enable: false },
+    { type: 'text', text: '[quote=]' }
+  ]);
+  // the author limit is in characters, not utf-16 code units
+  expect(parse('[quote=' + '😀'.repeat(64) + ']')).toEqual([
+    { type: 'quote', enable: true, author: '😀'.repeat(64) }
+  ]);
+  expect(parse('[quote=' + '😀'.repeat(65) + ']')[0]!.type).toBe('text');
+});
+
+test('length: with code', () => {
+  expect(length(parse('[code][b]hi[/b][/code]'))).toBe(9);
+});
+
 test('length: normal text', () => {
   expect(length(parse('hello'))).toBe(5);
   expect(length(parse('hello\nworld'))).toBe(11);
@@ -10,8 +10,16 @@ export enum StyleKind {
   UNDERLINE = 'u',
   /** Strikethrough text `[s]` - renders as `<del>` */
   STRIKETHROUGH = 's',
-  /** Link `[url]` - renders as `<a href="...">` */
+  /** Link `[url]` - renders as `<a href="...">`, takes its href from its text unless opened with a `LinkPart` */
   LINK = 'url',
+  /** Spoiler `[spoiler]` - renders as `<span class="sillycode-spoiler">`, hidden until clicked */
+  SPOILER = 'spoiler',
+  /** Superscript text `[sup]` - renders as `<sup>` */
+  SUPERSCRIPT = 'sup',
+  /** Subscript text `[sub]` - renders as `<sub>` */
+  SUBSCRIPT = 'sub',
+  /** Small text `[small]` - renders as `<small>` */
+  SMALL = 'small',
 }
 
 /**
@@ -74,6 +82,30 @@ export type EmotePart = {
   emote: EmoteKind;
 };
 
+/** Link with an explicit href `[url=...]`, closed by `[/url]` like `StyleKind.LINK`. */
+export type LinkPart = {
+  type: 'link';
+  href: string;
+};
+
+/** Inline code `[code]...[/code]` on a single line, its content is taken verbatim. */
+export type CodePart = {
+  type: 'code';
+  code: string;
+};
+
+/** Code block `[codeblock]...[/codeblock]`, which can span several lines, its content is taken verbatim. */
+export type CodeBlockPart = {
+  type: 'codeblock';
+  code: string;
+};
+
+/** Quote block toggle `[quote]` or `[quote=author]`, enable or disable, acts as a stack. */
+export type QuotePart = { type: 'quote' } & (
+  { enable: true, author?: string } |
+  { enable: false }
+);
+
 /**
  * A single element of parsed sillycode markup.
  */
@@ -83,7 +115,11 @@ export type Part =
   | NewlinePart
   | StylePart
   | ColorPart
-  | EmotePart;
+  | EmotePart
+  | LinkPart
+  | CodePart
+  | CodeBlockPart
+  | QuotePart;
 
 
 /** lookup table for style tags like [b] or [/i] */
@@ -99,6 +135,10 @@ addStyle('i', StyleKind.ITALIC);
 addStyle('u', StyleKind.UNDERLINE);
 addStyle('s', StyleKind.STRIKETHROUGH);
 addStyle('url', StyleKind.LINK);
+addStyle('spoiler', StyleKind.SPOILER);
+addStyle('sup', StyleKind.SUPERSCRIPT);
+addStyle('sub', StyleKind.SUBSCRIPT);
+addStyle('small', StyleKind.SMALL);
 
 /** lookup table for emote tags like [:)] or [:D] */
 var emoteParts = Object.create(null) as Record<string, EmotePart>;
@@ -169,9 +209,66 @@ export function parse(input: string): Part[] {
       return { type: 'color', enable: false };
     }
 
+    // check for link with href, at most 2048 characters long:
+    if (body.length > 4 && body.startsWith('url=') && countUnicodeScalars(body) <= 2048) {
+      return { type: 'link', href: body.slice(4) };
+    }
+
+    // check for quote push:
+    if (body === 'quote') {
+      return { type: 'quote', enable: true };
+    }
+    if (body.length > 6 && body.startsWith('quote=') && countUnicodeScalars(body) <= 70) {
+      return { type: 'quote', enable: true, author: body.slice(6) };
+    }
+
+    // check for quote pop:
+    if (body === '/quote') {
+      return { type: 'quote', enable: false };
+    }
+
+    // check for code, the content is filled in by attemptTag:
+    if (body === 'code') {
+      return { type: 'code', code: '' };
+    }
+
+    // check for code block, the content is filled in by attemptTag:
+    if (body === 'codeblock') {
+      return { type: 'codeblock', code: '' };
+    }
+
     return null;
   }
 
+  // remembers the result of the last search for a pattern, so that searches
+  // from later positions do not scan the same input again
+  function scanner(pattern: string): (start: number, limit: number) => number {
+    // where the last search started and stopped, and its first match
+    var from = 0;
+    var to = 0;
+    var found = -1;
+
+    // finds the first match of the pattern that lies within input.slice(start, limit)
+    return function (start: number, limit: number): number {
+      // the last search is still valid if it started earlier and either
+      // found a match at or after the start, or searched at least as far
+      var known = from <= start && (found >= 0 ? found >= start : limit <= to);
+      if (!known) {
+        from = start;
+        to = limit;
+        found = input.slice(start, limit).indexOf(pattern);
+        if (found >= 0) {
+          found += start;
+        }
+      }
+      return found >= 0 && found + pattern.length <= limit ? found : -1;
+    };
+  }
+
+  var findNewline = scanner('\n');
+  var findCode = scanner('[/code]');
+  var findCodeBlock = scanner('[/codeblock]');
+
   // attempts to parse a tag at the current position
   function attemptTag(): boolean {
     // find the last opening bracket
@@ -192,6 +289,25 @@ export function parse(input: string): Part[] {
     // parse the tag
     var part = parseTag(body);
 
+    // code is verbatim until its closing tag, inline code is only a tag if it is closed on the same line
+    if (part && (part.type === 'code' || part.type === 'codeblock')) {
+      var close = '[/' + part.type + ']';
+      var start = i + 1;
+      var end: number;
+      if (part.type === 'code') {
+        var line = findNewline(start, input.length);
+        end = findCode(start, line < 0 ? input.length : line);
+      } else {
+        end = findCodeBlock(start, input.length);
+      }
+      if (end < 0) {
+        return false;
+      }
+      part = { type: part.type, code: input.slice(start, end) };
+      // skip over the content and the closing tag
+      i = end + close.length - 1;
+    }
+
     // if we parsed a tag
     if (part) {
       // remove the tag from the buffer
@@ -281,6 +397,8 @@ export function length(parts: Part[]): number {
   parts.forEach(function (part) {
     if (part.type === 'text') {
       length += countUnicodeScalars(part.text);
+    } else if (part.type === 'code' || part.type === 'codeblock') {
+      length += countUnicodeScalars(part.code);
     } else if (part.type === 'newline' || part.type === 'emote') {
       length += 1;
     }
@@ -47,9 +47,60 @@ test('render: link ignores emote', () => {
     .toBe('<div><a href="https://face:">face: <img class="sillycode-emote" src="/static/emoticons/sad.png" alt="sad"></a></div>');
 });
 
+test('render: link with href', () => {
+  expect(render(parse('[url=https://example.com]click [b]here[/b][/url]')))
+    .toBe('<div><a href="https://example.com">click <strong>here</strong></a></div>');
+  expect(render(parse('[url=example.com]click[/url]'), true))
+    .toBe('<div><span class="sillycode-meta">[url=example.com]</span><a href="https://example.com">click</a><span class="sillycode-meta">[/url]</span></div>');
+});
+
+test('render: code', () => {
+  expect(render(parse('[code]<b>[:3][/code]')))
+    .toBe('<div><code>&lt;b&gt;[:3]</code></div>');
+  expect(render(parse('[code]x[/code]'), true))
+    .toBe('<div><span class="sillycode-meta">[code]</span><code>x</code><span class="sillycode-meta">[/code]</span></div>');
+});
+
+test('render: code block', () => {
+  expect(render(parse('[b]a [codeblock]  x [b]\n\n  <y> [/codeblock] b[/b]')))
+    .toBe('<div><strong>a </strong><pre><code>  x [b]\n\n  &lt;y&gt; </code></pre><strong> b</strong></div>');
+});
+
+test('render: code block in spoiler', () => {
+  expect(render(parse('[spoiler]the killer is [b][codeblock]Bob[/codeblock][/b][/spoiler]')))
+    .toBe('<div><span class="sillycode-spoiler">the killer is <strong></strong></span>' +
+      '<span class="sillycode-spoiler"><pre><code>Bob</code></pre></span>' +
+      '<span class="sillycode-spoiler"><strong></strong></span></div>');
+});
+
+test('render: quotes', () => {
+  expect(render(parse('a [b]x[quote=lua <3]hi\n[quote]nested[/quote][/quote]y[/b]')))
+    .toBe('<div>a <strong>x</strong></div><blockquote><cite>lua &lt;3</cite><div><strong>hi</strong></div><blockquote><div><strong>nested</strong></div></blockquote></blockquote><div><strong>y</strong></div>');
+  expect(render(parse('a\n[quote]q\n[/quote]\nb')))
+    .toBe('<div>a</div><blockquote><div>q</div></blockquote><div>b</div>');
+  expect(render(parse('[quote]unclosed')))
+    .toBe('<blockquote><div>unclosed</div></blockquote>');
+  expect(render(parse('[quote=lua]hi[/quote]'), true))
+    .toBe('<div><span class="sillycode-meta">[quote=lua]</span>hi<span class="sillycode-meta">[/quote]</span></div>');
+});
+
+test('render: spoiler', () => {
+  expect(render(parse('[spoiler]a [b]b[/spoiler]c[/b]')))
+    .toBe('<div><span class="sillycode-spoiler">a <strong>b</strong></span><strong>c</strong></div>');
+  expect(render(parse('[spoiler]x[/spoiler]'), true))
+    .toBe('<div><span class="sillycode-meta">[spoiler]</span><span class="sillycode-spoiler">x</span><span class="sillycode-meta">[/spoiler]</span></div>');
+});
+
+test('render: sup, sub and small', () => {
+  expect(render(parse('x[sup]2[/sup] H[sub]2[/sub]O [small]fine [b]print[/small]![/b]')))
+    .toBe('<div>x<sup>2</sup> H<sub>2</sub>O <small>fine <strong>print</strong></small><strong>!</strong></div>');
+});
+
 test('render: nested links', () => {
   expect(render(parse('[url]this is a link: [url]https://example.com[/url][/url]')))
-    .toBe('<div><a href="https://this is a link: https://example.com">this is a link: <a href="https://example.com">https://example.com</a></a></div>');
+    .toBe('<div><a href="https://this is a link:">this is a link: </a><a href="https://example.com">https://example.com</a></div>');
+  expect(render(parse('[url]a.com[url]b.com[/url][/url]'), true))
+    .toBe('<div><span class="sillycode-meta">[url]</span><a href="https://a.com">a.com</a><span class="sillycode-meta">[url]</span><a href="https://b.com">b.com</a><span class="sillycode-meta">[/url]</span><span class="sillycode-meta">[/url]</span></div>');
 });
 
 test('render: multiple colors', () => {
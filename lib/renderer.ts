@@ -1,5 +1,5 @@
 import { EmoteKind, StyleKind } from './parser.ts';
-import type { ColorPart, Color, EmotePart, EscapePart, NewlinePart, Part, StylePart, TextPart } from './parser.ts';
+import type { CodeBlockPart, CodePart, ColorPart, Color, EmotePart, EscapePart, LinkPart, NewlinePart, Part, QuotePart, StylePart, TextPart } from './parser.ts';
 
 var escapeHtmlRegex = /[&<>"']/g;
 
@@ -36,13 +36,15 @@ function escapeHref(href: string): string {
 /** represents a reference to the `href` field of a link in the outputted HTML */
 interface Link {
   href: string;
+  explicit: boolean;
   replacer: string;
 }
 
-/** creates a new link with the given id */
-function createLink(id: number): Link {
+/** creates a new link with the given id, and an explicit href if given */
+function createLink(id: number, href?: string): Link {
   return {
-    href: '',
+    href: href || '',
+    explicit: href != null,
     replacer: '§§HREF' + id + '§§'
   };
 }
@@ -53,6 +55,10 @@ type Element =
   | { name: 'em' }
   | { name: 'ins' }
   | { name: 'del' }
+  | { name: 'spoiler' }
+  | { name: 'sup' }
+  | { name: 'sub' }
+  | { name: 'small' }
   | { name: 'span', color: Color }
   | { name: 'a', link: Link };
 
@@ -92,12 +98,40 @@ export function render(parts: Part[], isEditor?: boolean): string {
   // clist of all links
   var linkList: Link[] = [];
 
+  // number of open quotes
+  var quoteCounter = 0;
+  // index in the html where the current line starts
+  var lineStart = 0;
+  // whether the current line has any content
+  var lineContent = false;
+  // whether the current line starts right after a quote
+  var afterQuote = false;
+
+  // starts a new line
+  function startLine(): void {
+    lineStart = html.length;
+    lineContent = false;
+    html += '<div>';
+  }
+
+  // ends the current line, or drops it if it has no content and
+  // dropEmpty is true, since quotes already start a new block
+  function endLine(dropEmpty: boolean): void {
+    if (dropEmpty && !lineContent) {
+      html = html.slice(0, lineStart);
+    } else {
+      html += '</div>';
+    }
+  }
+
   // opens an element
   function open(element: Element): void {
     if (element.name === 'span') {
       html += '<span style="color: ' + element.color + '">';
     } else if (element.name === 'a') {
       html += '<a href="' + element.link.replacer + '">';
+    } else if (element.name === 'spoiler') {
+      html += '<span class="sillycode-spoiler">';
     } else {
       html += '<' + element.name + '>';
     }
@@ -105,7 +139,7 @@ export function render(parts: Part[], isEditor?: boolean): string {
 
   // closes an element
   function close(element: Element): void {
-    html += '</' + element.name + '>';
+    html += '</' + (element.name === 'spoiler' ? 'span' : element.name) + '>';
   }
 
   // opens all elements in the element stack
@@ -160,7 +194,7 @@ export function render(parts: Part[], isEditor?: boolean): string {
 
   // applies a specific style element,
   // possibly pushing/popping from the element stack
-  function apply(name: 'strong' | 'em' | 'ins' | 'del', enable: boolean): void {
+  function apply(name: 'strong' | 'em' | 'ins' | 'del' | 'spoiler' | 'sup' | 'sub' | 'small', enable: boolean): void {
     if (enable) {
       if (!contains(name)) {
         push({ name: name });
@@ -172,17 +206,17 @@ export function render(parts: Part[], isEditor?: boolean): string {
 
   // creates a new link and adds it to the link list,
   // then pushes it to the element stack
-  function pushLink(): void {
-    var link = createLink(linkCounter);
+  function pushLink(href?: string): void {
+    var link = createLink(linkCounter, href);
     linkCounter += 1;
     linkList.push(link);
     push({ name: 'a', link: link });
   }
 
-  // appends to all links in the element stack
+  // appends to all links in the element stack without an explicit href
   function appendLink(text: string): void {
     elements.forEach(function (element) {
-      if (element.name === 'a') {
+      if (element.name === 'a' && !element.link.explicit) {
         element.link.href += text;
       }
     });
@@ -203,6 +237,7 @@ export function render(parts: Part[], isEditor?: boolean): string {
 
     // append the text to the HTML output
     html += text;
+    lineContent = lineContent || text.length > 0;
 
     // update the link hrefs
     appendLink(text)
@@ -220,8 +255,11 @@ export function render(parts: Part[], isEditor?: boolean): string {
     // close all elements used for styling to get back to the root of the tree
     closeAll(elements);
 
-    // close and open a new div to start a new line
-    html += '</div><div>';
+    // close and open a new div to start a new line,
+    // a line break right after a quote only ends the line the quote started
+    endLine(afterQuote);
+    startLine();
+    afterQuote = false;
 
     // re-open all elements
     openAll(elements);
@@ -232,6 +270,8 @@ export function render(parts: Part[], isEditor?: boolean): string {
     // links are a special case
     if (part.style === StyleKind.LINK) {
       if (part.enable) {
+        // links never nest, opening a link closes the one that is already open
+        remove('a');
         meta('[url]');
         pushLink();
       } else {
@@ -252,6 +292,14 @@ export function render(parts: Part[], isEditor?: boolean): string {
         apply('ins', part.enable);
       } else if (part.style === StyleKind.STRIKETHROUGH) {
         apply('del', part.enable);
+      } else if (part.style === StyleKind.SPOILER) {
+        apply('spoiler', part.enable);
+      } else if (part.style === StyleKind.SUPERSCRIPT) {
+        apply('sup', part.enable);
+      } else if (part.style === StyleKind.SUBSCRIPT) {
+        apply('sub', part.enable);
+      } else if (part.style === StyleKind.SMALL) {
+        apply('small', part.enable);
       }
 
       if (!part.enable) {
@@ -271,8 +319,93 @@ export function render(parts: Part[], isEditor?: boolean): string {
     }
   }
 
+  // handles link parts with an explicit href
+  function onLink(part: LinkPart): void {
+    // escape the href for HTML, like the text collected by other links
+    var href = escapeHtml(part.href);
+
+    // links never nest, opening a link closes the one that is already open
+    remove('a');
+    meta('[url=' + href + ']');
+    pushLink(href);
+  }
+
+  // handles code parts
+  function onCode(part: CodePart): void {
+    // escape the code for HTML
+    var code = escapeHtml(part.code);
+
+    // append the code to the HTML output
+    lineContent = true;
+    meta('[code]');
+    html += '<code>' + code + '</code>';
+    meta('[/code]');
+
+    // code is visible text too, so it goes into the link hrefs
+    appendLink(code);
+  }
+
+  // handles code block parts
+  function onCodeBlock(part: CodeBlockPart): void {
+    // close all elements, a block can't be inside of them
+    closeAll(elements);
+
+    // append the code block to the HTML output, its whitespace is kept
+    // as is since it never touches the line divs, and it is still hidden
+    // if it is in a spoiler
+    var spoiler = contains('spoiler');
+    if (spoiler) {
+      open({ name: 'spoiler' });
+    }
+    lineContent = true;
+    meta('[codeblock]');
+    html += '<pre><code>' + escapeHtml(part.code) + '</code></pre>';
+    meta('[/codeblock]');
+    if (spoiler) {
+      close({ name: 'spoiler' });
+    }
+
+    // re-open all elements
+    openAll(elements);
+  }
+
+  // handles quote parts
+  function onQuote(part: QuotePart): void {
+    if (part.enable) {
+      meta(part.author != null ? '[quote=' + escapeHtml(part.author) + ']' : '[quote]');
+    }
+
+    // quotes are blocks, so all elements are closed around their boundaries,
+    // and closing tags without an open quote do nothing.
+    // the editor only shows the tags so that every line stays a direct child
+    if (!isEditor && (part.enable || quoteCounter > 0)) {
+      closeAll(elements);
+      endLine(true);
+
+      if (part.enable) {
+        quoteCounter += 1;
+        html += '<blockquote>';
+        if (part.author != null) {
+          html += '<cite>' + escapeHtml(part.author) + '</cite>';
+        }
+      } else {
+        quoteCounter -= 1;
+        html += '</blockquote>';
+      }
+
+      startLine();
+      afterQuote = !part.enable;
+      openAll(elements);
+    }
+
+    if (!part.enable) {
+      meta('[/quote]');
+    }
+  }
+
   // handles emote parts
   function onEmote(part: EmotePart): void {
+    lineContent = true;
     if (isEditor) {
       html += '<span class="sillycode-emote" style="background-image: url(/static/emoticons/' + part.emote + '.png)">[' + emoteKindToTag[part.emote] + ']</span>';
     } else {
@@ -281,7 +414,7 @@ export function render(parts: Part[], isEditor?: boolean): string {
   }
 
   // start the output
-  html += '<div>';
+  startLine();
 
   // render the parts
   parts.forEach(function (part) {
@@ -292,14 +425,26 @@ export function render(parts: Part[], isEditor?: boolean): string {
       case 'style': onStyle(part); break;
       case 'color': onColor(part); break;
       case 'emote': onEmote(part); break;
+      case 'link': onLink(part); break;
+      case 'code': onCode(part); break;
+      case 'codeblock': onCodeBlock(part); break;
+      case 'quote': onQuote(part); break;
     }
   });
 
   // close all elements
   closeAll(elements);
 
+  // close all open quotes
+  for (; quoteCounter > 0; quoteCounter--) {
+    endLine(true);
+    html += '</blockquote>';
+    startLine();
+    afterQuote = true;
+  }
+
   // close the output
-  html += '</div>';
+  endLine(afterQuote);
 
   // replace all link references with the actual hrefs
   linkList.forEach(function (link) {
@@ -0,0 +1,47 @@
+//! Compile-time sillycode parsing and rendering.
+//!
+//! The [sillycode!] macro parses markup while compiling and expands to the
+//! rendered HTML as a `&'static str`, so fixed messages cost nothing at runtime.
+//!
+//! # Usage
+//!
+//! ```rust
+//! use sillycode_macros::sillycode;
+//!
+//! const WELCOME: &str = sillycode!("[b]Welcome[/b] [:3]");
+//!
+//! assert_eq!(WELCOME, "<div><strong>Welcome</strong> <img class=\"sillycode-emote\" src=\"/static/emoticons/colonthree.png\" alt=\"colonthree\"></div>");
+//! ```
+//!
+//! Markup is checked in strict mode, so unbalanced tags fail to compile:
+//!
+//! ```rust,compile_fail
+//! use sillycode_macros::sillycode;
+//!
+//! const BROKEN: &str = sillycode!("[b]Welcome");
+//! ```
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, LitStr};
+
+/// Parses sillycode markup at compile time and expands to the rendered HTML.
+///
+/// The markup must pass [sillycode::check], any strict-mode error is reported
+/// as a compile error on the string literal.
+#[proc_macro]
+pub fn sillycode(input: TokenStream) -> TokenStream {
+  let literal = parse_macro_input!(input as LitStr);
+
+  match sillycode::parse_strict(&literal.value()) {
+    Ok(parts) => {
+      let html = sillycode::render(parts, false);
+      quote!(#html).into()
+    }
+    Err(error) => {
+      syn::Error::new(literal.span(), format!("invalid sillycode: {error}"))
+        .to_compile_error()
+        .into()
+    }
+  }
+}
@@ -0,0 +1,12 @@
+use sillycode_macros::sillycode;
+
+#[test]
+fn test_macro_renders_html() {
+  assert_eq!(sillycode!("[b]Welcome[/b] [:3]"), sillycode::render(sillycode::parse("[b]Welcome[/b] [:3]"), false));
+}
+
+#[test]
+fn test_macro_is_const() {
+  const HTML: &str = sillycode!("[color=#ff0000]red[/color]\nline two");
+  assert_eq!(HTML, "<div><span style=\"color: #ff0000\">red</span></div><div>line two</div>");
+}
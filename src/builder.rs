@@ -0,0 +1,183 @@
+use std::fmt;
+
+use crate::link::encode_href;
+use crate::parser::*;
+
+/// Fluent builder for constructing sillycode programmatically.
+///
+/// Every style, color and link opened by the builder is closed again, and all
+/// text is escaped, so the output always parses back to the same parts.
+///
+/// ```rust
+/// use sillycode::{SillycodeBuilder, EmoteKind};
+///
+/// let markup = SillycodeBuilder::new()
+///   .bold(|b| b.text("Welcome"))
+///   .text(" [user]! ")
+///   .emote(EmoteKind::ColonThree)
+///   .to_string();
+///
+/// assert_eq!(markup, "[b]Welcome[/b] [user]! [:3]");
+/// ```
+#[derive(Default, Debug, Clone)]
+pub struct SillycodeBuilder {
+  /// output parts
+  parts: Vec<Part>,
+  /// buffer for text that has not been escaped yet
+  buffer: String,
+  /// styles that are currently enabled
+  styles: Vec<StyleKind>,
+}
+
+impl SillycodeBuilder {
+
+  /// Creates a new empty builder.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// escapes and emits the buffered text, if there is any
+  fn flush(&mut self) {
+    if !self.buffer.is_empty() {
+      self.parts.extend(escape_parts(&self.buffer));
+      self.buffer.clear();
+    }
+  }
+
+  /// emits a new part
+  fn emit(&mut self, part: Part) {
+    self.flush();
+    self.parts.push(part);
+  }
+
+  /// Adds plain text, which is escaped so it is never interpreted as markup.
+  ///
+  /// Newlines in the text become [Part::Newline].
+  pub fn text(mut self, text: &str) -> Self {
+    self.buffer.push_str(text);
+    self
+  }
+
+  /// Adds a line break.
+  pub fn newline(mut self) -> Self {
+    self.emit(Part::Newline);
+    self
+  }
+
+  /// Adds an emoticon.
+  pub fn emote(mut self, emote: EmoteKind) -> Self {
+    self.emit(Part::Emote(emote));
+    self
+  }
+
+  /// Wraps the content built by `build` in the given style.
+  ///
+  /// If the style is already enabled it is left as is, since styles toggle
+  /// independently and closing a nested copy would end the outer one early.
+  pub fn style(mut self, style: StyleKind, build: impl FnOnce(Self) -> Self) -> Self {
+    if self.styles.contains(&style) {
+      return build(self);
+    }
+
+    self.emit(Part::Style(style, true));
+    self.styles.push(style);
+
+    self = build(self);
+
+    self.styles.retain(|s| *s != style);
+    self.emit(Part::Style(style, false));
+
+    self
+  }
+
+  /// Wraps the content built by `build` in bold `[b]`.
+  pub fn bold(self, build: impl FnOnce(Self) -> Self) -> Self {
+    self.style(StyleKind::Bold, build)
+  }
+
+  /// Wraps the content built by `build` in italics `[i]`.
+  pub fn italic(self, build: impl FnOnce(Self) -> Self) -> Self {
+    self.style(StyleKind::Italic, build)
+  }
+
+  /// Wraps the content built by `build` in underline `[u]`.
+  pub fn underline(self, build: impl FnOnce(Self) -> Self) -> Self {
+    self.style(StyleKind::Underline, build)
+  }
+
+  /// Wraps the content built by `build` in strikethrough `[s]`.
+  pub fn strikethrough(self, build: impl FnOnce(Self) -> Self) -> Self {
+    self.style(StyleKind::Strikethrough, build)
+  }
+
+  /// Wraps the content built by `build` in a spoiler `[spoiler]`.
+  pub fn spoiler(self, build: impl FnOnce(Self) -> Self) -> Self {
+    self.style(StyleKind::Spoiler, build)
+  }
+
+  /// Wraps the content built by `build` in superscript `[sup]`.
+  pub fn superscript(self, build: impl FnOnce(Self) -> Self) -> Self {
+    self.style(StyleKind::Superscript, build)
+  }
+
+  /// Wraps the content built by `build` in subscript `[sub]`.
+  pub fn subscript(self, build: impl FnOnce(Self) -> Self) -> Self {
+    self.style(StyleKind::Subscript, build)
+  }
+
+  /// Wraps the content built by `build` in small text `[small]`.
+  pub fn small(self, build: impl FnOnce(Self) -> Self) -> Self {
+    self.style(StyleKind::Small, build)
+  }
+
+  /// Wraps the content built by `build` in the given color.
+  pub fn color(mut self, color: Color, build: impl FnOnce(Self) -> Self) -> Self {
+    self.emit(Part::Color(color, true));
+    self = build(self);
+    self.emit(Part::Color(Color::default(), false));
+    self
+  }
+
+  /// Adds a link to `url` with the content built by `build`.
+  ///
+  /// The link is written as `[url=...]`, and if `build` adds nothing the url
+  /// itself is used as the text. Brackets, backslashes and newlines in the
+  /// url are percent-encoded. Links never nest, inside of another link, or if
+  /// the url is empty or too long for a tag, only the content is added.
+  pub fn link(mut self, url: &str, build: impl FnOnce(Self) -> Self) -> Self {
+    let part = match Part::parse_link_tag(&format!("url={}", encode_href(url))) {
+      Some(part) if !self.styles.contains(&StyleKind::Link) => part,
+      _ => return build(self),
+    };
+
+    self.emit(part);
+    self.styles.push(StyleKind::Link);
+
+    let start = self.parts.len();
+    self = build(self);
+    if self.parts.len() == start && self.buffer.is_empty() {
+      self.buffer.push_str(url);
+    }
+
+    self.styles.retain(|s| *s != StyleKind::Link);
+    self.emit(Part::Style(StyleKind::Link, false));
+
+    self
+  }
+
+  /// Finishes building and returns the parts.
+  pub fn build(mut self) -> Vec<Part> {
+    self.flush();
+    self.parts
+  }
+
+}
+
+impl fmt::Display for SillycodeBuilder {
+
+  /// Formats the built parts as sillycode markup.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}{}", to_markup(&self.parts), escape_markup(&self.buffer))
+  }
+
+}
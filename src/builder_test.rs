@@ -0,0 +1,120 @@
+#[cfg(test)]
+mod tests {
+
+  use crate::parser::*;
+  use crate::builder::*;
+
+  #[test]
+  fn test_build_nothing() {
+    assert_eq!(SillycodeBuilder::new().build(), vec![]);
+  }
+
+  #[test]
+  fn test_build_styles_and_emote() {
+    assert_eq!(
+      SillycodeBuilder::new()
+        .bold(|b| b.text("BE EXTRA ").italic(|b| b.text("SILLY")))
+        .text(" ")
+        .emote(EmoteKind::ColonD)
+        .build(),
+      parse("[b]BE EXTRA [i]SILLY[/i][/b] [:D]")
+    );
+  }
+
+  #[test]
+  fn test_build_spoiler() {
+    assert_eq!(
+      SillycodeBuilder::new().spoiler(|b| b.text("it was ").bold(|b| b.text("him"))).build(),
+      parse("[spoiler]it was [b]him[/b][/spoiler]")
+    );
+  }
+
+  #[test]
+  fn test_build_sup_sub_small() {
+    assert_eq!(
+      SillycodeBuilder::new()
+        .text("x")
+        .superscript(|b| b.text("2"))
+        .text(" H")
+        .subscript(|b| b.text("2"))
+        .text("O ")
+        .small(|b| b.text("fine print"))
+        .build(),
+      parse("x[sup]2[/sup] H[sub]2[/sub]O [small]fine print[/small]")
+    );
+  }
+
+  #[test]
+  fn test_build_escapes_text() {
+    let builder = SillycodeBuilder::new()
+      .bold(|b| b.text("[/b]"))
+      .text(" said \\ [:)]");
+    assert_eq!(builder.to_string(), "[b][/b\\][/b] said \\\\ [:)\\]");
+    assert_eq!(parse(&builder.to_string()), builder.build());
+  }
+
+  #[test]
+  fn test_build_escapes_text_across_calls() {
+    let builder = SillycodeBuilder::new().text("[b").text("]");
+    assert_eq!(builder.to_string(), "[b\\]");
+  }
+
+  #[test]
+  fn test_build_nested_same_style() {
+    assert_eq!(
+      SillycodeBuilder::new()
+        .bold(|b| b.text("a").bold(|b| b.text("b")).text("c"))
+        .to_string(),
+      "[b]abc[/b]"
+    );
+  }
+
+  #[test]
+  fn test_build_colors_and_newlines() {
+    assert_eq!(
+      SillycodeBuilder::new()
+        .color(Color::new(255, 0, 0), |b| b.text("red").newline().color(Color::new(0, 255, 0), |b| b.text("green")))
+        .to_string(),
+      "[color=#ff0000]red\n[color=#00ff00]green[/color][/color]"
+    );
+  }
+
+  #[test]
+  fn test_build_link() {
+    assert_eq!(
+      SillycodeBuilder::new()
+        .link("https://example.com", |b| b)
+        .to_string(),
+      "[url=https://example.com]https://example.com[/url]"
+    );
+    assert_eq!(
+      SillycodeBuilder::new()
+        .link("https://example.com", |b| b.bold(|b| b.text("click here")))
+        .to_string(),
+      "[url=https://example.com][b]click here[/b][/url]"
+    );
+    assert_eq!(
+      SillycodeBuilder::new()
+        .link("example.com/[a]\\b", |b| b.text("[b]"))
+        .to_string(),
+      "[url=example.com/%5Ba%5D%5Cb][b\\][/url]"
+    );
+    assert_eq!(
+      SillycodeBuilder::new()
+        .link("", |b| b.text("no link"))
+        .to_string(),
+      "no link"
+    );
+  }
+
+  #[test]
+  fn test_build_nested_link() {
+    assert_eq!(
+      SillycodeBuilder::new()
+        .link("https://example.com", |b| b.link("https://example.org", |b| b.text("example.com")))
+        .to_string(),
+      "[url=https://example.com]example.com[/url]"
+    );
+  }
+
+}
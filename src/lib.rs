@@ -18,9 +18,24 @@
 
 pub mod parser;
 pub mod renderer;
+pub mod builder;
+pub mod transform;
+pub mod runs;
+pub mod link;
+#[cfg(feature = "serde")]
+mod serialize;
 
 mod parser_test;
 mod renderer_test;
+mod builder_test;
+mod transform_test;
+mod runs_test;
+mod link_test;
+mod serialize_test;
 
-pub use parser::{parse, length, Part, StyleKind, EmoteKind, Color};
-pub use renderer::render;
+pub use parser::{parse, parse_strict, check, StrictError, to_markup, escape_markup, escape_parts, length, length_with, LengthMetric, LengthOptions, Part, StyleKind, EmoteKind, Color, ParseColorError};
+pub use renderer::{render, render_with, RenderOptions, RenderOutput, ElementMap, HtmlTag, render_plain, RenderBackend, RenderEngine, Element};
+pub use builder::SillycodeBuilder;
+pub use transform::{truncate, truncate_with, TruncateOptions, split, split_with, split_markup, split_markup_with, normalize};
+pub use runs::{to_runs, from_runs, Run, RunContent};
+pub use link::{autolink, links, deception, Deception, LinkInfo, LinkPolicy, RewriteFn};
@@ -0,0 +1,500 @@
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+use unicode_security::{skeleton, MixedScript};
+use url::Url;
+
+use crate::parser::*;
+use crate::renderer::{Element, RenderBackend, RenderEngine};
+use crate::transform::{Open, OpenParts};
+
+/// resolves the text collected by a link into a valid http(s) URL, adding the
+/// https protocol if it's not there, or returns `None` if it's not a valid URL
+pub(crate) fn escape_href(href: &str) -> Option<String> {
+  // trim the input
+  let href = href.trim();
+
+  // add the https protocol if it's not there (allows http too!)
+  let has_protocol = |protocol: &str| href.get(..protocol.len()).is_some_and(|p| p.eq_ignore_ascii_case(protocol));
+  let href = if !has_protocol("http://") && !has_protocol("https://") {
+    format!("https://{}", href)
+  } else {
+    href.to_string()
+  };
+
+  // parse the url, this percent-encodes illegal characters and converts the host to punycode
+  let url = Url::parse(&href).ok()?;
+  if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none_or(str::is_empty) {
+    return None;
+  }
+
+  // don't add a trailing slash to bare hosts like "example.com"
+  let mut result = String::from(url);
+  if !href.ends_with('/') && result.ends_with('/') && href.matches('/').count() == 2 {
+    result.pop();
+  }
+  Some(result)
+}
+
+/// percent-encodes the characters that can't be written inside of a
+/// `[url=...]` tag, which are brackets, backslashes and newlines
+pub(crate) fn encode_href(href: &str) -> String {
+  href
+    .replace('[', "%5B")
+    .replace(']', "%5D")
+    .replace('\\', "%5C")
+    .replace('\n', "%0A")
+}
+
+/// Function that rewrites an href, see [LinkPolicy::rewrite].
+pub type RewriteFn = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Policy applied to every link by the HTML renderer.
+///
+/// The policy works on the final href of a link, after its text has been
+/// collected and parsed as a URL. Links whose host is not allowed are
+/// rendered as plain text, the same as links that are not valid http(s) URLs.
+///
+/// The default policy adds no `rel` attribute, use [LinkPolicy::user_content]
+/// to mark links in user generated content with `rel="nofollow ugc noopener"`.
+#[derive(Default, Clone)]
+pub struct LinkPolicy {
+  /// Value of the `rel` attribute added to every link, if any.
+  pub rel: Option<String>,
+  /// Whether links open in a new tab, using `target="_blank"`.
+  pub new_tab: bool,
+  /// Hosts that links may point to, `None` allows every host.
+  /// Subdomains of a listed host are allowed too.
+  pub allow_hosts: Option<Vec<String>>,
+  /// Hosts that links may not point to, including their subdomains.
+  /// Checked before [LinkPolicy::allow_hosts].
+  pub deny_hosts: Vec<String>,
+  /// Function that rewrites every allowed href, for example to route
+  /// outbound links through a redirect page. It can capture settings like
+  /// the base URL of that page.
+  pub rewrite: Option<RewriteFn>,
+  /// Whether to show the real host after links that are deceptive, see
+  /// [LinkInfo::deception], like `<span class="sillycode-link-host">(evil.com)</span>`.
+  /// Has no effect in the editor.
+  pub show_deceptive_hosts: bool,
+}
+
+impl PartialEq for LinkPolicy {
+
+  /// compares two policies, rewrite functions are compared by address
+  fn eq(&self, other: &Self) -> bool {
+    let rewrite = match (&self.rewrite, &other.rewrite) {
+      (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+      (a, b) => a.is_none() && b.is_none(),
+    };
+    rewrite
+      && self.rel == other.rel
+      && self.new_tab == other.new_tab
+      && self.allow_hosts == other.allow_hosts
+      && self.deny_hosts == other.deny_hosts
+      && self.show_deceptive_hosts == other.show_deceptive_hosts
+  }
+
+}
+
+impl fmt::Debug for LinkPolicy {
+
+  /// formats the policy, the rewrite function is only shown as being set
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("LinkPolicy")
+      .field("rel", &self.rel)
+      .field("new_tab", &self.new_tab)
+      .field("allow_hosts", &self.allow_hosts)
+      .field("deny_hosts", &self.deny_hosts)
+      .field("rewrite", &self.rewrite.as_ref().map(|_| "Fn"))
+      .field("show_deceptive_hosts", &self.show_deceptive_hosts)
+      .finish()
+  }
+
+}
+
+impl LinkPolicy {
+
+  /// Returns a policy suited for user generated content, marking every link
+  /// with `rel="nofollow ugc noopener"`.
+  pub fn user_content() -> Self {
+    Self { rel: Some("nofollow ugc noopener".to_string()), ..Self::default() }
+  }
+
+  /// Checks if links may point to a host.
+  pub fn allows(&self, host: &str) -> bool {
+    let host = normalize_host(host);
+    let matches = |entry: &String| {
+      let entry = normalize_host(entry);
+      host == entry || host.strip_suffix(&entry).is_some_and(|rest| rest.ends_with('.'))
+    };
+
+    if self.deny_hosts.iter().any(matches) {
+      return false;
+    }
+
+    match &self.allow_hosts {
+      Some(hosts) => hosts.iter().any(matches),
+      None => true,
+    }
+  }
+
+  /// Resolves the text collected by a link into its final href, or returns
+  /// `None` if the link is not a valid http(s) URL or is not allowed.
+  pub fn resolve(&self, text: &str) -> Option<String> {
+    let href = escape_href(text)?;
+
+    if !self.allows(Url::parse(&href).ok()?.host_str()?) {
+      return None;
+    }
+
+    match &self.rewrite {
+      Some(rewrite) => Some(rewrite(&href)),
+      None => Some(href),
+    }
+  }
+
+}
+
+/// converts a host to the lowercase punycode form used in URLs, if possible
+fn normalize_host(host: &str) -> String {
+  Url::parse(&format!("https://{host}"))
+    .ok()
+    .and_then(|url| url.host_str().map(str::to_string))
+    .unwrap_or_else(|| host.to_lowercase())
+}
+
+/// prefixes that start a bare URL
+const URL_PREFIXES: [&str; 3] = ["https://", "http://", "www."];
+
+/// checks if a character ends a bare URL
+fn ends_url(char: char) -> bool {
+  char.is_whitespace() || matches!(char, '<' | '>' | '"' | '[' | ']' | '\\' | '`')
+}
+
+/// longest bare URL that can still be written as a `[url=...]` tag
+const MAX_URL_LENGTH: usize = 2048 - "url=".len();
+
+/// returns the length of the bare URL at the start of text, without
+/// trailing punctuation and unbalanced closing parentheses, and the length
+/// of the whole word it is in
+fn url_length(text: &str) -> (usize, usize) {
+  let end = text.find(ends_url).unwrap_or(text.len());
+  let mut url = &text[..end];
+
+  // count the parentheses once, so trimming stays linear
+  let mut unbalanced = url.matches(')').count() as isize - url.matches('(').count() as isize;
+  loop {
+    match url.chars().last() {
+      Some('.' | ',' | ':' | ';' | '!' | '?' | '\'' | '*') => url = &url[..url.len() - 1],
+      Some(')') if unbalanced > 0 => {
+        url = &url[..url.len() - 1];
+        unbalanced -= 1;
+      }
+      _ => break,
+    }
+  }
+
+  (url.len(), end)
+}
+
+/// finds the first bare URL in text, returning its byte range
+fn find_url(text: &str) -> Option<(usize, usize)> {
+  let mut skip = 0;
+
+  for (start, _) in text.char_indices() {
+    // URLs have to start at the beginning of a word
+    if start < skip || text[..start].chars().last().is_some_and(|c| c.is_alphanumeric() || matches!(c, '.' | '/' | '@')) {
+      continue;
+    }
+
+    let rest = &text[start..];
+    let prefix = URL_PREFIXES.iter().find(|prefix| {
+      rest.get(..prefix.len()).is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+    });
+
+    if let Some(prefix) = prefix {
+      let (length, word) = url_length(rest);
+      let url = &rest[..length];
+      // check the length first, so that long words are never parsed
+      if url.len() > prefix.len() && url.chars().count() <= MAX_URL_LENGTH && escape_href(url).is_some() {
+        return Some((start, start + length));
+      }
+      // the rest of the word can't start another URL, skipping it keeps this linear
+      skip = start + word;
+    }
+  }
+
+  None
+}
+
+/// Turns bare http(s) URLs and `www.` hosts in text into links.
+///
+/// Each URL becomes a `[url=...]` link with the URL as its text, so it goes
+/// through the same href resolution and [LinkPolicy] as any other link.
+/// Trailing punctuation like a full stop is not part of the URL, and neither
+/// is a closing parenthesis without a matching opening one. Text that is
+/// already inside a link is left alone.
+pub fn autolink(parts: &[Part]) -> Vec<Part> {
+  let mut output = Vec::new();
+  let mut open = OpenParts::default();
+
+  for part in parts {
+    open.apply(part);
+
+    let Part::Text(text) = part else {
+      output.push(part.clone());
+      continue;
+    };
+    if open.stack.iter().any(|o| matches!(o, Open::Link(_, _))) {
+      output.push(part.clone());
+      continue;
+    }
+
+    let mut rest = text.as_str();
+    while let Some((start, end)) = find_url(rest) {
+      if start > 0 {
+        output.push(Part::Text(rest[..start].to_string()));
+      }
+      let url = &rest[start..end];
+      output.push(Part::Link(url.to_string()));
+      output.push(Part::Text(url.to_string()));
+      output.push(Part::Style(StyleKind::Link, false));
+      rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+      output.push(Part::Text(rest.to_string()));
+    }
+  }
+
+  output
+}
+
+/// A link found by [links].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkInfo {
+  /// Resolved href, exactly like the rendered `href` before any [LinkPolicy]
+  /// is applied, or `None` if the link is not a valid http(s) URL and renders
+  /// as plain text.
+  pub href: Option<String>,
+  /// Visible text of the link, including inline code, without emotes and
+  /// line breaks.
+  pub text: String,
+  /// Byte range of the link in the markup, from its opening tag to the tag
+  /// that closes it, see [to_markup].
+  pub span: Range<usize>,
+}
+
+impl LinkInfo {
+
+  /// Checks if the link is deceptive, see [deception].
+  pub fn deception(&self) -> Option<Deception> {
+    deception(self.href.as_deref()?, &self.text)
+  }
+
+}
+
+/// render backend that only tracks links, which the engine does by itself
+struct LinkBackend;
+
+impl RenderBackend for LinkBackend {
+
+  /// nothing to output
+  fn open(&mut self, _element: &Element) {}
+
+  /// nothing to output
+  fn close(&mut self, _element: &Element) {}
+
+  /// nothing to output
+  fn text(&mut self, _text: &str) {}
+
+  /// nothing to output
+  fn emote(&mut self, _emote: EmoteKind) {}
+
+  /// nothing to output
+  fn line_break(&mut self) {}
+
+}
+
+/// returns the id of the link that is open in the engine, if there is one
+fn open_link(engine: &RenderEngine<LinkBackend>) -> Option<usize> {
+  engine.elements().iter().find_map(|element| match element {
+    Element::A { id } => Some(*id),
+    _ => None,
+  })
+}
+
+/// Finds every link in parts, in order.
+///
+/// The hrefs are resolved with the same text accumulation as the renderer, so
+/// they are exactly what users click. Bare URLs are only included if the
+/// parts were passed through [autolink] first.
+pub fn links(parts: &[Part]) -> Vec<LinkInfo> {
+  let mut engine = RenderEngine::new(LinkBackend);
+  let mut infos: Vec<LinkInfo> = Vec::new();
+  let mut offset = 0;
+
+  for part in parts {
+    let end = offset + part.to_string().len();
+    let before = open_link(&engine);
+    engine.part(part);
+    let after = open_link(&engine);
+
+    if let Some(id) = before.filter(|id| after != Some(*id)) {
+      // a new link closes the previous one right before its tag
+      infos[id].span.end = if after.is_some() { offset } else { end };
+    }
+    if after.is_some() && after != before {
+      infos.push(LinkInfo { href: None, text: String::new(), span: offset..end });
+    }
+    if let (Some(id), Part::Text(text) | Part::Code(text)) = (after, part) {
+      infos[id].text.push_str(text);
+    }
+
+    offset = end;
+  }
+
+  // links that are never closed last until the end
+  if let Some(id) = open_link(&engine) {
+    infos[id].span.end = offset;
+  }
+
+  for (info, href) in infos.iter_mut().zip(engine.links()) {
+    info.href = escape_href(href);
+  }
+
+  infos
+}
+
+/// Reason why a link is deceptive, returned by [deception].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Deception {
+  /// The text of the link shows a URL or domain with a different host than
+  /// the href, like `paypal.com` linking to `evil.com`.
+  HostMismatch {
+    /// Host shown in the text.
+    shown: String,
+    /// Real host of the href.
+    host: String,
+  },
+  /// The host of the href mixes scripts in one label, or is made entirely of
+  /// characters that look like ASCII, like a Cyrillic `раураl.com`.
+  Homoglyph {
+    /// Real host of the href, in punycode.
+    host: String,
+    /// ASCII host that it looks like, if there is one.
+    looks_like: Option<String>,
+  },
+}
+
+impl Deception {
+
+  /// Returns the real host of the href.
+  pub fn host(&self) -> &str {
+    match self {
+      Deception::HostMismatch { host, .. } => host,
+      Deception::Homoglyph { host, .. } => host,
+    }
+  }
+
+}
+
+/// generic top level domains that bare domains in text are recognized by,
+/// sorted so they can be binary searched
+const GENERIC_TLDS: [&str; 64] = [
+  "aero", "app", "art", "asia", "bank", "bar", "biz", "blog", "buzz", "cat",
+  "chat", "click", "cloud", "club", "com", "coop", "design", "dev", "edu", "email",
+  "fun", "games", "gay", "gov", "info", "ink", "int", "jobs", "life", "link",
+  "live", "lol", "media", "mil", "mobi", "moe", "museum", "name", "net", "network",
+  "news", "one", "online", "org", "page", "pro", "shop", "site", "social", "space",
+  "store", "stream", "studio", "systems", "tech", "tel", "today", "top", "travel", "website",
+  "wiki", "world", "xyz", "zone",
+];
+
+/// two letter extensions of common file names, like "README.md", which are
+/// not recognized as country code top level domains in bare domains
+const FILE_EXTENSIONS: [&str; 17] = [
+  "cc", "cs", "db", "gz", "hs", "js", "kt", "md", "ml", "ps",
+  "py", "rb", "rs", "sh", "so", "ts", "xz",
+];
+
+/// checks if a token of text looks like a domain, returning its host
+///
+/// tokens with a protocol or "www." only need a top level domain made of
+/// letters, bare domains need a known one so that file names don't count
+fn shown_host(token: &str) -> Option<String> {
+  let token = token.trim_matches(|c: char| !c.is_alphanumeric());
+  if !token.contains('.') {
+    return None;
+  }
+
+  let url = Url::parse(&escape_href(token)?).ok()?;
+  let host = url.host_str()?;
+
+  let tld = host.rsplit('.').next()?;
+  if tld.starts_with("xn--") {
+    return Some(host.to_string());
+  }
+  if tld.len() < 2 || !tld.chars().all(|c| c.is_ascii_alphabetic()) {
+    return None;
+  }
+
+  let marked = URL_PREFIXES.iter().any(|prefix| token.get(..prefix.len()).is_some_and(|p| p.eq_ignore_ascii_case(prefix)));
+  let known = match tld.len() {
+    2 => FILE_EXTENSIONS.binary_search(&tld).is_err(),
+    _ => GENERIC_TLDS.binary_search(&tld).is_ok(),
+  };
+  (marked || known).then(|| host.to_string())
+}
+
+/// checks if two hosts belong to the same site, either one can be a subdomain of the other
+fn same_site(a: &str, b: &str) -> bool {
+  let a = a.strip_prefix("www.").unwrap_or(a);
+  let b = b.strip_prefix("www.").unwrap_or(b);
+  let subdomain = |sub: &str, parent: &str| sub.strip_suffix(parent).is_some_and(|rest| rest.ends_with('.'));
+  a == b || subdomain(a, b) || subdomain(b, a)
+}
+
+/// Checks if a link with the given resolved href and visible text is deceptive.
+///
+/// A link is deceptive if its text contains something that looks like a URL
+/// or domain for a different site than the href, or if the host of the href
+/// uses homoglyphs, characters from several scripts or characters that look
+/// like ASCII, to imitate another host.
+pub fn deception(href: &str, text: &str) -> Option<Deception> {
+  let url = Url::parse(href).ok()?;
+  let host = url.host_str()?;
+
+  // check the host for homoglyphs, label by label
+  let (unicode, _) = idna::domain_to_unicode(host);
+  let mut looks_like = String::new();
+  let mut suspicious = false;
+  for (i, label) in unicode.split('.').enumerate() {
+    if i > 0 {
+      looks_like.push('.');
+    }
+    if label.is_ascii() {
+      looks_like.push_str(label);
+      continue;
+    }
+    let lookalike: String = skeleton(label).collect();
+    suspicious |= !label.is_single_script() || lookalike.is_ascii();
+    looks_like.push_str(&lookalike);
+  }
+  if suspicious {
+    let looks_like = looks_like.is_ascii().then_some(looks_like);
+    return Some(Deception::Homoglyph { host: host.to_string(), looks_like });
+  }
+
+  // check if the text shows a different host
+  for token in text.split_whitespace() {
+    if let Some(shown) = shown_host(token) {
+      if !same_site(&shown, host) {
+        return Some(Deception::HostMismatch { shown, host: host.to_string() });
+      }
+    }
+  }
+
+  None
+}
@@ -0,0 +1,206 @@
+#[cfg(test)]
+mod tests {
+
+  use std::sync::Arc;
+
+  use crate::link::*;
+  use crate::parser::*;
+
+  /// autolinks markup and converts it back to markup
+  fn autolink_markup(input: &str) -> String {
+    to_markup(&autolink(&parse(input)))
+  }
+
+  #[test]
+  fn test_link_policy_default() {
+    let policy = LinkPolicy::default();
+    assert_eq!(policy.resolve("example.com"), Some("https://example.com".to_string()));
+    assert_eq!(policy.resolve(" http://example.com/a "), Some("http://example.com/a".to_string()));
+  }
+
+  #[test]
+  fn test_escape_href() {
+    assert_eq!(escape_href("example.com"), Some("https://example.com".to_string()));
+    assert_eq!(escape_href("example.com/"), Some("https://example.com/".to_string()));
+    assert_eq!(escape_href("HTTP://Example.COM/Path"), Some("http://example.com/Path".to_string()));
+    assert_eq!(escape_href("example.com/ä b#<x>"), Some("https://example.com/%C3%A4%20b#%3Cx%3E".to_string()));
+    assert_eq!(escape_href("ドメイン.テスト/パス"), Some("https://xn--eckwd4c7c.xn--zckzah/%E3%83%91%E3%82%B9".to_string()));
+    assert_eq!(escape_href("bold! wow!"), None);
+    assert_eq!(escape_href("javascript:alert(1)"), None);
+    assert_eq!(escape_href(""), None);
+  }
+
+  #[test]
+  fn test_link_policy_idn_hosts() {
+    let policy = LinkPolicy { deny_hosts: vec!["Bücher.de".to_string()], ..LinkPolicy::default() };
+    assert_eq!(policy.resolve("shop.bücher.de"), None);
+    assert_eq!(policy.resolve("xn--bcher-kva.de"), None);
+    assert_eq!(policy.resolve("buecher.de"), Some("https://buecher.de".to_string()));
+  }
+
+  #[test]
+  fn test_link_policy_deny_hosts() {
+    let policy = LinkPolicy { deny_hosts: vec!["evil.com".to_string()], ..LinkPolicy::default() };
+    assert_eq!(policy.resolve("evil.com/path"), None);
+    assert_eq!(policy.resolve("https://www.EVIL.com"), None);
+    assert_eq!(policy.resolve("https://user@evil.com:8080/"), None);
+    assert_eq!(policy.resolve("notevil.com"), Some("https://notevil.com".to_string()));
+    assert_eq!(policy.resolve("evil.com.example.org"), Some("https://evil.com.example.org".to_string()));
+  }
+
+  #[test]
+  fn test_link_policy_allow_hosts() {
+    let policy = LinkPolicy {
+      allow_hosts: Some(vec!["sillypost.net".to_string()]),
+      deny_hosts: vec!["bad.sillypost.net".to_string()],
+      ..LinkPolicy::default()
+    };
+    assert_eq!(policy.resolve("sillypost.net"), Some("https://sillypost.net".to_string()));
+    assert_eq!(policy.resolve("cdn.sillypost.net/a.png"), Some("https://cdn.sillypost.net/a.png".to_string()));
+    assert_eq!(policy.resolve("bad.sillypost.net"), None);
+    assert_eq!(policy.resolve("example.com?sillypost.net"), None);
+  }
+
+  #[test]
+  fn test_link_policy_rewrite() {
+    let base = "https://sillypost.net/out".to_string();
+    let policy = LinkPolicy {
+      rewrite: Some(Arc::new(move |href| format!("{base}?u={}", href.replace(':', "%3A").replace('/', "%2F")))),
+      deny_hosts: vec!["evil.com".to_string()],
+      ..LinkPolicy::default()
+    };
+    assert_eq!(policy.resolve("example.com/a"), Some("https://sillypost.net/out?u=https%3A%2F%2Fexample.com%2Fa".to_string()));
+    assert_eq!(policy.resolve("evil.com"), None);
+    // rewrite functions are compared by address
+    assert_eq!(policy.clone(), policy);
+    assert_ne!(LinkPolicy { rewrite: Some(Arc::new(str::to_string)), ..LinkPolicy::default() }, policy);
+  }
+
+  #[test]
+  fn test_autolink() {
+    assert_eq!(autolink_markup("see https://example.com/a?b=1 now"), "see [url=https://example.com/a?b=1]https://example.com/a?b=1[/url] now");
+    assert_eq!(autolink_markup("WWW.example.com and http://a.com"), "[url=WWW.example.com]WWW.example.com[/url] and [url=http://a.com]http://a.com[/url]");
+    assert_eq!(autolink_markup("[b]bold www.a.com[/b]"), "[b]bold [url=www.a.com]www.a.com[/url][/b]");
+  }
+
+  #[test]
+  fn test_autolink_trailing_punctuation() {
+    assert_eq!(autolink_markup("go to www.a.com."), "go to [url=www.a.com]www.a.com[/url].");
+    assert_eq!(autolink_markup("really? https://a.com/?!"), "really? [url=https://a.com/]https://a.com/[/url]?!");
+    assert_eq!(autolink_markup("(see https://a.com/x)"), "(see [url=https://a.com/x]https://a.com/x[/url])");
+    assert_eq!(autolink_markup("https://en.wikipedia.org/wiki/Fox_(disambiguation)."), "[url=https://en.wikipedia.org/wiki/Fox_(disambiguation)]https://en.wikipedia.org/wiki/Fox_(disambiguation)[/url].");
+  }
+
+  #[test]
+  fn test_autolink_ignores() {
+    // not at the start of a word, not a valid host, or too short
+    assert_eq!(autolink_markup("xhttps://a.com a.www.b.com https:// www. https://a b"), "xhttps://a.com a.www.b.com https:// www. [url=https://a]https://a[/url] b");
+    // already inside a link
+    assert_eq!(autolink_markup("[url]https://a.com[/url] [url=b.com]www.c.com[/url]"), "[url]https://a.com[/url] [url=b.com]www.c.com[/url]");
+    // stops at brackets
+    assert_eq!(autolink_markup("[https://a.com]"), "[[url=https://a.com]https://a.com[/url]]");
+    // too long to be written as a tag
+    let long = format!("https://a.com/{}", "x".repeat(2048));
+    assert_eq!(autolink_markup(&long), long);
+  }
+
+  #[test]
+  fn test_autolink_long_words() {
+    // every candidate in a word used to be parsed up to the end of it
+    let input = "(https://a.b".repeat(50_000);
+    assert_eq!(autolink(&parse(&input)), vec![Part::Text(input)]);
+    let input = format!("https://a.com/{} www.b.com", ")".repeat(100_000));
+    assert_eq!(autolink(&parse(&input)).len(), 7);
+  }
+
+  #[test]
+  fn test_links() {
+    let input = "see [url]example.com/[b]a[/b][:)][/url] and [url=b.com]click\nhere[/url] [url]bold! wow![/url]";
+    assert_eq!(
+      links(&parse(input)),
+      vec![
+        LinkInfo { href: Some("https://example.com/a".to_string()), text: "example.com/a".to_string(), span: 4..39 },
+        LinkInfo { href: Some("https://b.com".to_string()), text: "clickhere".to_string(), span: 44..71 },
+        LinkInfo { href: None, text: "bold! wow!".to_string(), span: 72..93 },
+      ]
+    );
+    assert_eq!(&input[4..39], "[url]example.com/[b]a[/b][:)][/url]");
+    assert_eq!(&input[44..71], "[url=b.com]click\nhere[/url]");
+  }
+
+  #[test]
+  fn test_links_nested_and_unclosed() {
+    let input = "[url]a.com[url=b.com]b [i]c";
+    let found = links(&parse(input));
+    assert_eq!(found.iter().map(|l| l.href.as_deref()).collect::<Vec<_>>(), vec![Some("https://a.com"), Some("https://b.com")]);
+    assert_eq!(found.iter().map(|l| l.text.as_str()).collect::<Vec<_>>(), vec!["a.com", "b c"]);
+    assert_eq!(found.iter().map(|l| &input[l.span.clone()]).collect::<Vec<_>>(), vec!["[url]a.com", "[url=b.com]b [i]c"]);
+  }
+
+  #[test]
+  fn test_links_with_code() {
+    let found = links(&parse("[url=evil.com][code]paypal.com[/code][/url]"));
+    assert_eq!(found[0].text, "paypal.com");
+    assert_eq!(found[0].deception(), Some(Deception::HostMismatch { shown: "paypal.com".to_string(), host: "evil.com".to_string() }));
+  }
+
+  #[test]
+  fn test_links_after_autolink() {
+    let found = links(&autolink(&parse("bare www.a.com")));
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].href.as_deref(), Some("https://www.a.com"));
+    assert_eq!(found[0].text, "www.a.com");
+  }
+
+  #[test]
+  fn test_deception_host_mismatch() {
+    assert_eq!(
+      deception("https://evil.com/login", "log in at https://paypal.com now"),
+      Some(Deception::HostMismatch { shown: "paypal.com".to_string(), host: "evil.com".to_string() })
+    );
+    assert_eq!(
+      deception("https://evil.com", "(www.PayPal.com)"),
+      Some(Deception::HostMismatch { shown: "www.paypal.com".to_string(), host: "evil.com".to_string() })
+    );
+    assert_eq!(deception("https://www.example.com/a", "example.com"), None);
+    assert_eq!(deception("https://docs.example.com", "see example.com/docs"), None);
+    assert_eq!(deception("https://evil.com", "click here, version 1.2 or e.g. file"), None);
+    assert_eq!(
+      deception("https://evil.com", "sillypost.net or paypal.de"),
+      Some(Deception::HostMismatch { shown: "sillypost.net".to_string(), host: "evil.com".to_string() })
+    );
+  }
+
+  #[test]
+  fn test_deception_ignores_file_names() {
+    for text in ["read CONTRIBUTING.md", "see index.html", "built with Node.js", "file.txt", "main.rs and setup.py"] {
+      assert_eq!(deception("https://github.com/luavixen/sillycode", text), None, "{text}");
+    }
+    // unless they are written as a url
+    assert!(deception("https://github.com", "https://readme.md").is_some());
+  }
+
+  #[test]
+  fn test_deception_homoglyph() {
+    // cyrillic letters that look like "paypal"
+    let href = escape_href("раураl.com").unwrap();
+    assert_eq!(
+      deception(&href, "раураl.com"),
+      Some(Deception::Homoglyph { host: "xn--l-7sba6dbr.com".to_string(), looks_like: Some("paypal.com".to_string()) })
+    );
+    // latin mixed with cyrillic in one label
+    assert!(matches!(deception(&escape_href("exаmple.com").unwrap(), "x"), Some(Deception::Homoglyph { .. })));
+    // normal international domains are fine
+    assert_eq!(deception(&escape_href("bücher.de").unwrap(), "bücher.de"), None);
+    assert_eq!(deception(&escape_href("пример.рф").unwrap(), "пример.рф"), None);
+  }
+
+  #[test]
+  fn test_link_info_deception() {
+    let found = links(&parse("[url=evil.com]paypal.com[/url] [url]paypal.com[/url] [url=?]paypal.com[/url]"));
+    assert_eq!(found[0].deception().map(|d| d.host().to_string()), Some("evil.com".to_string()));
+    assert_eq!(found[1].deception(), None);
+    assert_eq!(found[2].deception(), None);
+  }
+
+}
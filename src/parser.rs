@@ -1,6 +1,9 @@
-use std::fmt;
+use std::fmt::{self, Write};
+use std::str::FromStr;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 /// Styling options for text formatting.
 #[derive(EnumIter, Debug, Clone, Copy, PartialEq)]
@@ -13,8 +16,18 @@ pub enum StyleKind {
   Underline,
   /// Strikethrough text `[s]` - renders as `<del>`
   Strikethrough,
-  /// Link `[url]` - renders as `<a href="...">`
+  /// Link `[url]` - renders as `<a href="...">`, takes its href from its text
+  /// unless it was opened with [Part::Link]
   Link,
+  /// Spoiler `[spoiler]` - renders as `<span class="sillycode-spoiler">`,
+  /// hidden until clicked
+  Spoiler,
+  /// Superscript text `[sup]` - renders as `<sup>`
+  Superscript,
+  /// Subscript text `[sub]` - renders as `<sub>`
+  Subscript,
+  /// Small text `[small]` - renders as `<small>`
+  Small,
 }
 
 impl StyleKind {
@@ -27,6 +40,10 @@ impl StyleKind {
       StyleKind::Underline => "u",
       StyleKind::Strikethrough => "s",
       StyleKind::Link => "url",
+      StyleKind::Spoiler => "spoiler",
+      StyleKind::Superscript => "sup",
+      StyleKind::Subscript => "sub",
+      StyleKind::Small => "small",
     }
   }
 
@@ -94,6 +111,15 @@ impl EmoteKind {
 }
 
 /// RGB color value with 8-bit components.
+///
+/// Colors parsed from markup remember which of their hex digits were written
+/// in uppercase, so that formatting them reproduces the original markup.
+/// The case is part of equality, so `#AD77F1` and `#ad77f1` are different
+/// colors that only differ in their markup, compare the components to check
+/// for the same RGB value. Rendered HTML always uses lowercase.
+///
+/// Because of the hidden case, colors can't be built with a struct literal
+/// like `Color { r, g, b }` anymore, use [Color::new] instead.
 #[derive(Default, Debug, Clone, Copy, PartialEq)]
 pub struct Color {
   /// Red component (0-255).
@@ -102,13 +128,61 @@ pub struct Color {
   pub g: u8,
   /// Blue component (0-255).
   pub b: u8,
+  /// bitmask of hex digits that were written in uppercase
+  case: u8,
 }
 
 impl Color {
 
   /// Creates a new color from RGB components.
   pub const fn new(r: u8, g: u8, b: u8) -> Self {
-    Self { r, g, b }
+    Self { r, g, b, case: 0 }
+  }
+
+  /// parses exactly six hex digits like "ad77f1", remembering their case
+  fn parse_hex(hex: &str) -> Option<Self> {
+    let digits = hex.as_bytes();
+    if digits.len() != 6 || !digits.iter().all(u8::is_ascii_hexdigit) {
+      return None;
+    }
+
+    let mut case = 0;
+    for (i, digit) in digits.iter().enumerate() {
+      if digit.is_ascii_uppercase() {
+        case |= 1 << i;
+      }
+    }
+
+    let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
+    let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
+    let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
+
+    Some(Self { r, g, b, case })
+  }
+
+}
+
+/// Error returned when parsing a [Color] from a string fails.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseColorError;
+
+impl fmt::Display for ParseColorError {
+
+  /// Formats the error as a human readable message.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "expected a color like \"#ad77f1\"")
+  }
+
+}
+
+impl std::error::Error for ParseColorError {}
+
+impl FromStr for Color {
+  type Err = ParseColorError;
+
+  /// Parses a hexadecimal string like `"#ad77f1"`, the inverse of [Color]'s [fmt::Display].
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    s.strip_prefix('#').and_then(Self::parse_hex).ok_or(ParseColorError)
   }
 
 }
@@ -117,7 +191,24 @@ impl fmt::Display for Color {
 
   /// Formats the color as a hexadecimal string like `"#ad77f1"`.
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    let nibbles = [
+      self.r >> 4, self.r & 15,
+      self.g >> 4, self.g & 15,
+      self.b >> 4, self.b & 15,
+    ];
+
+    f.write_char('#')?;
+
+    for (i, nibble) in nibbles.into_iter().enumerate() {
+      let digit = char::from_digit(nibble as u32, 16).unwrap();
+      if self.case & (1 << i) != 0 {
+        f.write_char(digit.to_ascii_uppercase())?;
+      } else {
+        f.write_char(digit)?;
+      }
+    }
+
+    Ok(())
   }
 
 }
@@ -137,6 +228,17 @@ pub enum Part {
   Color(Color, bool),
   /// Emoticon image.
   Emote(EmoteKind),
+  /// Link with an explicit href `[url=...]`, closed by `[/url]` like [StyleKind::Link].
+  Link(String),
+  /// Inline code `[code]...[/code]` on a single line, its content is taken
+  /// verbatim without interpreting tags, emotes or escapes.
+  Code(String),
+  /// Code block `[codeblock]...[/codeblock]`, which can span several lines,
+  /// its content is taken verbatim like [Part::Code].
+  CodeBlock(String),
+  /// Quote block toggle `[quote]` or `[quote=author]`, enable or disable,
+  /// acts as a stack like [Part::Color]. The author is `None` when disabling.
+  Quote(Option<String>, bool),
 }
 
 impl Part {
@@ -173,11 +275,8 @@ impl Part {
 
   /// parses a color tag body like "color=#ad77f1"
   fn parse_color_tag(body: &str) -> Option<Self> {
-    if body.len() == 13 && body.starts_with("color=#") {
-      let r = u8::from_str_radix(&body[ 7.. 9], 16).ok()?;
-      let g = u8::from_str_radix(&body[ 9..11], 16).ok()?;
-      let b = u8::from_str_radix(&body[11..13], 16).ok()?;
-      Some(Self::Color(Color::new(r, g, b), true))
+    if let Some(hex) = body.strip_prefix("color=#") {
+      Some(Self::Color(Color::parse_hex(hex)?, true))
     } else if body == "/color" {
       Some(Self::Color(Color::default(), false))
     } else {
@@ -185,15 +284,55 @@ impl Part {
     }
   }
 
+  /// parses a link tag body like "url=https://example.com", which can be
+  /// at most 2048 characters long
+  pub(crate) fn parse_link_tag(body: &str) -> Option<Self> {
+    match body.strip_prefix("url=") {
+      Some(href) if !href.is_empty() && body.chars().count() <= 2048 => Some(Self::Link(href.to_string())),
+      _ => None,
+    }
+  }
+
+  /// parses a quote tag body like "quote", "quote=lua" or "/quote", the
+  /// author can be at most 64 characters long
+  fn parse_quote_tag(body: &str) -> Option<Self> {
+    match body {
+      "quote" => Some(Self::Quote(None, true)),
+      "/quote" => Some(Self::Quote(None, false)),
+      _ => match body.strip_prefix("quote=") {
+        Some(author) if !author.is_empty() && author.chars().count() <= 64 => Some(Self::Quote(Some(author.to_string()), true)),
+        _ => None,
+      },
+    }
+  }
+
+  /// parses a code or code block tag body, the content is filled in by the parser
+  fn parse_code_tag(body: &str) -> Option<Self> {
+    match body {
+      "code" => Some(Self::Code(String::new())),
+      "codeblock" => Some(Self::CodeBlock(String::new())),
+      _ => None,
+    }
+  }
+
   /// parses any tag body
   fn parse_tag(body: &str) -> Option<Self> {
-    if body.is_empty() || body.len() > 32 {
+    if body.is_empty() {
       return None;
     }
 
+    // only links and quotes can have long tags, every other tag is ascii
+    // so its length in bytes is its length in characters
+    if body.len() > 32 {
+      return Self::parse_link_tag(body).or_else(|| Self::parse_quote_tag(body));
+    }
+
     Self::parse_style_tag(body)
       .or_else(|| Self::parse_emote_tag(body))
       .or_else(|| Self::parse_color_tag(body))
+      .or_else(|| Self::parse_link_tag(body))
+      .or_else(|| Self::parse_code_tag(body))
+      .or_else(|| Self::parse_quote_tag(body))
   }
 
 }
@@ -205,7 +344,7 @@ impl fmt::Display for Part {
     match self {
       Part::Text(text) => write!(f, "{text}"),
       Part::Escape => write!(f, "\\"),
-      Part::Newline => write!(f, "\n"),
+      Part::Newline => writeln!(f),
       Part::Style(style, enable) => {
         if *enable {
           write!(f, "[{}]", style.to_tag())
@@ -221,6 +360,12 @@ impl fmt::Display for Part {
         }
       }
       Part::Emote(emote) => write!(f, "[{}]", emote.to_tag()),
+      Part::Link(href) => write!(f, "[url={href}]"),
+      Part::Code(code) => write!(f, "[code]{code}[/code]"),
+      Part::CodeBlock(code) => write!(f, "[codeblock]{code}[/codeblock]"),
+      Part::Quote(Some(author), true) => write!(f, "[quote={author}]"),
+      Part::Quote(None, true) => write!(f, "[quote]"),
+      Part::Quote(_, false) => write!(f, "[/quote]"),
     }
   }
 
@@ -235,6 +380,44 @@ struct Parser {
   buffer: String,
   /// whether the previous character was an escape
   escape: bool,
+  /// search for the next newline
+  newline: Scan,
+  /// search for the next `[/code]`
+  code: Scan,
+  /// search for the next `[/codeblock]`
+  code_block: Scan,
+}
+
+/// remembers the result of the last search for a pattern, so that searches
+/// from later positions do not scan the same input again
+#[derive(Default, Debug)]
+struct Scan {
+  /// where the last search started
+  from: usize,
+  /// where the last search stopped
+  to: usize,
+  /// the first match of the last search
+  found: Option<usize>,
+}
+
+impl Scan {
+
+  /// finds the first match of the pattern that lies within `input[start..limit]`
+  fn find(&mut self, input: &str, pattern: &str, start: usize, limit: usize) -> Option<usize> {
+    // the last search is still valid if it started earlier and either
+    // found a match at or after the start, or searched at least as far
+    let known = self.from <= start && match self.found {
+      Some(found) => found >= start,
+      None => limit <= self.to,
+    };
+    if !known {
+      self.from = start;
+      self.to = limit;
+      self.found = input[start..limit].find(pattern).map(|found| start + found);
+    }
+    self.found.filter(|found| found + pattern.len() <= limit)
+  }
+
 }
 
 impl Parser {
@@ -257,44 +440,94 @@ impl Parser {
     }
   }
 
-  /// attempts to parse a tag at the current position
-  fn tag(&mut self) -> bool {
+  /// finds the tag that would be closed by a "]" at the current position,
+  /// returning the index of its opening bracket and the parsed part
+  fn find_tag(&self) -> Option<(usize, Part)> {
     // find the last opening bracket
-    let index = match self.buffer.rfind('[') {
-        Some(index) => index,
-        None => return false,
-    };
+    let index = self.buffer.rfind('[')?;
 
     // detect escape
     if index == 0 && matches!(self.parts.last(), Some(Part::Escape)) {
-      return false;
+      return None;
     }
 
     // extract the tag body
     let body = &self.buffer[index+1..];
 
     // parse the tag
-    let part = Part::parse_tag(body);
+    Part::parse_tag(body).map(|part| (index, part))
+  }
 
+  /// attempts to parse a tag ending right before the given position,
+  /// returning how many bytes of the input after it were used up
+  fn tag(&mut self, input: &str, position: usize) -> Option<usize> {
     // if we parsed a tag
-    if let Some(part) = part {
-      // remove the tag from the buffer
-      self.buffer.drain(index..);
-      // emit both the remaining buffer and the parsed part
-      self.flush();
-      self.emit(part);
-      // success!
-      true
-    } else {
-      // we did not parse a tag :<
-      false
+    let (index, mut part) = self.find_tag()?;
+    let mut used = 0;
+
+    // code is verbatim until its closing tag, inline code is only a tag if it is closed on the same line
+    let verbatim = match &mut part {
+      Part::Code(code) => {
+        let line = self.newline.find(input, "\n", position, input.len()).unwrap_or(input.len());
+        Some((code, "[/code]", self.code.find(input, "[/code]", position, line)))
+      }
+      Part::CodeBlock(code) => {
+        Some((code, "[/codeblock]", self.code_block.find(input, "[/codeblock]", position, input.len())))
+      }
+      _ => None,
+    };
+    if let Some((code, close, end)) = verbatim {
+      let end = end?;
+      code.push_str(&input[position..end]);
+      used = end + close.len() - position;
+    }
+
+    // remove the tag from the buffer
+    self.buffer.drain(index..);
+    // emit both the remaining buffer and the parsed part
+    self.flush();
+    self.emit(part);
+    // success!
+    Some(used)
+  }
+
+  /// emits an escape followed by an escaped character
+  fn escape(&mut self, char: char) {
+    self.flush();
+    self.emit(Part::Escape);
+    self.buffer.push(char);
+  }
+
+  /// converts plain text into parts that display it literally
+  fn escape_text(mut self, text: &str) -> Vec<Part> {
+    for char in text.chars() {
+      match char {
+        // backslashes always need escaping
+        '\\' => self.escape(char),
+        // closing brackets only need escaping if they would close a tag
+        ']' if self.find_tag().is_some() => self.escape(char),
+        // newlines are always their own part
+        '\n' => {
+          self.flush();
+          self.emit(Part::Newline);
+        }
+        // everything else is just text
+        _ => self.buffer.push(char),
+      }
     }
+
+    self.flush();
+    self.parts
   }
 
   /// parses sillycode markup
   fn parse(mut self, input: &str) -> Vec<Part> {
+    let mut position = 0;
+
     // main parsing loop
-    for char in input.chars() {
+    while let Some(char) = input[position..].chars().next() {
+      position += char.len_utf8();
+
       // if we are not escaping
       if !self.escape {
         // check for escape
@@ -306,7 +539,8 @@ impl Parser {
         }
         // check for tag close
         if char == ']' {
-          if self.tag() {
+          if let Some(used) = self.tag(input, position) {
+            position += used;
             continue;
           }
         }
@@ -340,13 +574,217 @@ pub fn parse(input: &str) -> Vec<Part> {
   Parser::new().parse(input)
 }
 
+/// Converts parsed parts back into sillycode markup.
+///
+/// This is the exact inverse of [parse], for any input `s`,
+/// `to_markup(&parse(s)) == s` always holds.
+pub fn to_markup(parts: &[Part]) -> String {
+  let mut markup = String::new();
+  for part in parts {
+    write!(&mut markup, "{part}").unwrap();
+  }
+  markup
+}
+
+/// Converts plain text into parts that display it literally.
+///
+/// [Part::Escape] is inserted only where the parser would otherwise recognize
+/// a tag or an escape, so the result contains nothing but text, escapes and
+/// newlines. Parsing the markup of the result gives back the same parts.
+pub fn escape_parts(text: &str) -> Vec<Part> {
+  Parser::new().escape_text(text)
+}
+
+/// Appends plain text to parts so that it displays literally.
+///
+/// Unlike extending with [escape_parts], the text is escaped in the context of
+/// the preceding parts, so it can never complete a tag started by them.
+pub(crate) fn push_text(parts: &mut Vec<Part>, text: &str) {
+  let mut parser = Parser::new();
+  parser.parts = std::mem::take(parts);
+
+  // continue from the trailing text, like the parser would
+  if let Some(Part::Text(_)) = parser.parts.last() {
+    if let Some(Part::Text(buffer)) = parser.parts.pop() {
+      parser.buffer = buffer;
+    }
+  }
+
+  *parts = parser.escape_text(text);
+}
+
+/// Escapes plain text so it can be embedded in sillycode markup.
+///
+/// Backslashes are inserted only where the parser would otherwise recognize
+/// a tag or an escape, so parsing the result yields a single run of text
+/// (and newlines) with exactly the original content.
+///
+/// ```rust
+/// use sillycode::escape_markup;
+///
+/// let name = "[/b]evil[:)]";
+/// assert_eq!(format!("[b]{}[/b] said", escape_markup(name)), "[b][/b\\]evil[:)\\][/b] said");
+/// ```
+pub fn escape_markup(text: &str) -> String {
+  to_markup(&escape_parts(text))
+}
+
+/// How text is measured when calculating the length of parts.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum LengthMetric {
+  /// Unicode scalar values, what [str::chars] yields.
+  #[default]
+  Scalars,
+  /// Extended grapheme clusters, so `"👨‍👩‍👧"` counts as one.
+  Graphemes,
+  /// UTF-16 code units, matching the browser `maxlength` attribute.
+  Utf16,
+  /// Terminal display width in columns, wide characters count as two.
+  Width,
+}
+
+impl LengthMetric {
+
+  /// Measures a string of text.
+  pub fn measure(&self, text: &str) -> usize {
+    match self {
+      LengthMetric::Scalars => text.chars().count(),
+      LengthMetric::Graphemes => text.graphemes(true).count(),
+      LengthMetric::Utf16 => text.encode_utf16().count(),
+      LengthMetric::Width => text.width(),
+    }
+  }
+
+}
+
+/// Options for [length_with].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LengthOptions {
+  /// How text is measured.
+  pub metric: LengthMetric,
+  /// Length of each emote.
+  pub emote: usize,
+  /// Length of each newline.
+  pub newline: usize,
+}
+
+impl Default for LengthOptions {
+
+  /// Returns the options used by [length], scalars with emotes and newlines counting as one.
+  fn default() -> Self {
+    Self { metric: LengthMetric::Scalars, emote: 1, newline: 1 }
+  }
+
+}
+
+impl LengthOptions {
+
+  /// Calculates the length of a single part.
+  pub fn measure(&self, part: &Part) -> usize {
+    match part {
+      Part::Text(text) | Part::Code(text) | Part::CodeBlock(text) => self.metric.measure(text),
+      Part::Newline => self.newline,
+      Part::Emote(_) => self.emote,
+      _ => 0,
+    }
+  }
+
+}
+
 /// Calculates the display length of parsed parts.
 pub fn length(parts: &[Part]) -> usize {
-  parts.iter().fold(0, |acc, part| {
-    match part {
-      Part::Text(text) => acc + text.chars().count(),
-      Part::Newline | Part::Emote(_) => acc + 1,
-      _ => acc,
+  length_with(parts, &LengthOptions::default())
+}
+
+/// Calculates the display length of parsed parts using the given options.
+pub fn length_with(parts: &[Part], options: &LengthOptions) -> usize {
+  parts.iter().map(|part| options.measure(part)).sum()
+}
+
+/// Problem found by [check] in markup that is not strictly well formed.
+///
+/// Each variant holds the index of the offending part.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StrictError {
+  /// Closing tag without a matching opening tag.
+  UnexpectedClose(usize),
+  /// Closing tag that does not close the most recently opened tag.
+  Misnested(usize),
+  /// Opening tag for a style that is already enabled.
+  AlreadyOpen(usize),
+  /// Opening tag that is never closed.
+  Unclosed(usize),
+  /// Escape at the end of the input that does not escape anything.
+  TrailingEscape(usize),
+}
+
+impl fmt::Display for StrictError {
+
+  /// Formats the error as a human readable message.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      StrictError::UnexpectedClose(index) => write!(f, "closing tag at part {index} was never opened"),
+      StrictError::Misnested(index) => write!(f, "closing tag at part {index} does not match the most recently opened tag"),
+      StrictError::AlreadyOpen(index) => write!(f, "opening tag at part {index} is already open"),
+      StrictError::Unclosed(index) => write!(f, "opening tag at part {index} is never closed"),
+      StrictError::TrailingEscape(index) => write!(f, "escape at part {index} does not escape anything"),
     }
-  })
+  }
+
+}
+
+impl std::error::Error for StrictError {}
+
+/// Checks that parsed parts are strictly well formed.
+///
+/// The parser and renderer accept any input, but in strict mode every style,
+/// color and quote must be closed exactly once, in the reverse order of
+/// opening, and the input must not end with a lone escape.
+pub fn check(parts: &[Part]) -> Result<(), StrictError> {
+  /// kind of an open tag, only styles can't be nested in themselves
+  #[derive(PartialEq)]
+  enum Tag {
+    Style(StyleKind),
+    Color,
+    Quote,
+  }
+
+  // stack of open tags as (index, kind)
+  let mut open: Vec<(usize, Tag)> = Vec::new();
+
+  for (index, part) in parts.iter().enumerate() {
+    let (tag, enable) = match part {
+      Part::Style(style, enable) => (Tag::Style(*style), *enable),
+      Part::Link(_) => (Tag::Style(StyleKind::Link), true),
+      Part::Color(_, enable) => (Tag::Color, *enable),
+      Part::Quote(_, enable) => (Tag::Quote, *enable),
+      Part::Escape if index + 1 == parts.len() => return Err(StrictError::TrailingEscape(index)),
+      _ => continue,
+    };
+
+    if enable {
+      if matches!(tag, Tag::Style(_)) && open.iter().any(|(_, t)| *t == tag) {
+        return Err(StrictError::AlreadyOpen(index));
+      }
+      open.push((index, tag));
+    } else {
+      match open.last() {
+        Some((_, t)) if *t == tag => { open.pop(); }
+        _ if open.iter().any(|(_, t)| *t == tag) => return Err(StrictError::Misnested(index)),
+        _ => return Err(StrictError::UnexpectedClose(index)),
+      }
+    }
+  }
+
+  match open.first() {
+    Some((index, _)) => Err(StrictError::Unclosed(*index)),
+    None => Ok(()),
+  }
+}
+
+/// Parses sillycode markup in strict mode, see [check].
+pub fn parse_strict(input: &str) -> Result<Vec<Part>, StrictError> {
+  let parts = parse(input);
+  check(&parts)?;
+  Ok(parts)
 }
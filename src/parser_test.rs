@@ -3,6 +3,7 @@
 mod tests {
 
   use crate::parser::*;
+  use proptest::prelude::*;
 
   #[test]
   fn test_parse_empty_string() {
@@ -143,4 +144,238 @@ mod tests {
     assert_eq!(length(&parse("this is a fox 🦊 from canada 🇨🇦")), 30);
   }
 
+  #[test]
+  fn test_length_with_metrics() {
+    let parts = parse("family: 👨‍👩‍👧 [b]漢字[/b]\n[:3]");
+    let options = |metric| LengthOptions { metric, ..LengthOptions::default() };
+    assert_eq!(length_with(&parts, &options(LengthMetric::Scalars)), 18);
+    assert_eq!(length_with(&parts, &options(LengthMetric::Graphemes)), 14);
+    assert_eq!(length_with(&parts, &options(LengthMetric::Utf16)), 21);
+    assert_eq!(length_with(&parts, &options(LengthMetric::Width)), 17);
+  }
+
+  #[test]
+  fn test_length_with_weights() {
+    let parts = parse("hi\n[:)][:(]");
+    assert_eq!(length_with(&parts, &LengthOptions { emote: 5, newline: 0, ..LengthOptions::default() }), 12);
+    assert_eq!(length_with(&parts, &LengthOptions::default()), length(&parts));
+  }
+
+  #[test]
+  fn test_parse_color_rejects_non_hex_digits() {
+    assert_eq!(parse("[color=#+f0000]"), vec![Part::Text("[color=#+f0000]".to_string())]);
+    assert_eq!(parse("[color=#aé000]"), vec![Part::Text("[color=#aé000]".to_string())]);
+  }
+
+  #[test]
+  fn test_parse_color_uppercase() {
+    let parts = parse("[color=#A834CF]");
+    let Part::Color(color, true) = parts[0] else { panic!("expected a color") };
+    assert_eq!((color.r, color.g, color.b), (168, 52, 207));
+    // the case is part of equality, so round trip bugs are caught
+    assert_ne!(parts, parse("[color=#a834cf]"));
+    assert_eq!(color, "#A834CF".parse().unwrap());
+  }
+
+  #[test]
+  fn test_parse_link_with_href() {
+    assert_eq!(
+      parse("[url=https://example.com/a-very-long-path?query=1]click [b]here[/b][/url]"),
+      vec![
+        Part::Link("https://example.com/a-very-long-path?query=1".to_string()),
+        Part::Text("click ".to_string()),
+        Part::Style(StyleKind::Bold, true),
+        Part::Text("here".to_string()),
+        Part::Style(StyleKind::Bold, false),
+        Part::Style(StyleKind::Link, false),
+      ]
+    );
+    assert_eq!(parse("[url=]"), vec![Part::Text("[url=]".to_string())]);
+    assert_eq!(parse("[url= a b ]"), vec![Part::Link(" a b ".to_string())]);
+    // the limit is in characters, not bytes
+    assert_eq!(parse(&format!("[url={}]", "é".repeat(2044))), vec![Part::Link("é".repeat(2044))]);
+    assert!(matches!(parse(&format!("[url={}]", "é".repeat(2045)))[0], Part::Text(_)));
+  }
+
+  #[test]
+  fn test_parse_code_is_verbatim() {
+    assert_eq!(
+      parse("a [code][b]x\\[:)][/code] [b]"),
+      vec![
+        Part::Text("a ".to_string()),
+        Part::Code("[b]x\\[:)]".to_string()),
+        Part::Text(" ".to_string()),
+        Part::Style(StyleKind::Bold, true),
+      ]
+    );
+    assert_eq!(parse("[code][/code]"), vec![Part::Code(String::new())]);
+    assert_eq!(parse("[code]unclosed"), vec![Part::Text("[code]unclosed".to_string())]);
+    assert_eq!(
+      parse("[code]a\nb[/code]"),
+      vec![Part::Text("[code]a".to_string()), Part::Newline, Part::Text("b[/code]".to_string())]
+    );
+    assert_eq!(parse("\\[code]x[/code]")[1], Part::Text("[code]x[/code]".to_string()));
+    assert_eq!(
+      parse("[code]a\n[code]b[/code] [code]c[/code]"),
+      vec![
+        Part::Text("[code]a".to_string()),
+        Part::Newline,
+        Part::Code("b".to_string()),
+        Part::Text(" ".to_string()),
+        Part::Code("c".to_string()),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_parse_unclosed_code_is_linear() {
+    // every opener used to scan the rest of the input for its closing tag
+    let input = format!("{}\n{}[/code]", "[code]".repeat(100_000), "x".repeat(1_000_000));
+    assert_eq!(parse(&input).len(), 3);
+    let input = format!("{}{}", "[code]".repeat(100_000), "x".repeat(1_000_000));
+    assert_eq!(parse(&input).len(), 1);
+  }
+
+  #[test]
+  fn test_parse_unclosed_code_block_is_linear() {
+    let input = format!("{}{}", "[codeblock]".repeat(100_000), "x".repeat(1_000_000));
+    assert_eq!(parse(&input).len(), 1);
+    let input = format!("{}[codeblock]a[/codeblock]", "[codeblock]\n".repeat(100_000));
+    assert_eq!(parse(&input), vec![Part::CodeBlock(format!("\n{}[codeblock]a", "[codeblock]\n".repeat(99_999)))]);
+  }
+
+  #[test]
+  fn test_parse_code_block() {
+    assert_eq!(
+      parse("[codeblock]\n  [b]x[/b] \\\n[/code][/codeblock]\n"),
+      vec![Part::CodeBlock("\n  [b]x[/b] \\\n[/code]".to_string()), Part::Newline]
+    );
+    assert_eq!(parse("[codeblock]unclosed\n"), vec![Part::Text("[codeblock]unclosed".to_string()), Part::Newline]);
+  }
+
+  #[test]
+  fn test_parse_quote() {
+    assert_eq!(
+      parse("[quote=a very long username that is fine]hi[/quote][quote][/quote]"),
+      vec![
+        Part::Quote(Some("a very long username that is fine".to_string()), true),
+        Part::Text("hi".to_string()),
+        Part::Quote(None, false),
+        Part::Quote(None, true),
+        Part::Quote(None, false),
+      ]
+    );
+    assert_eq!(parse("[quote=]"), vec![Part::Text("[quote=]".to_string())]);
+    assert_eq!(parse(&format!("[quote={}]", "x".repeat(65))).len(), 1);
+    assert!(matches!(parse(&format!("[quote={}]", "x".repeat(65)))[0], Part::Text(_)));
+    assert_eq!(parse(&format!("[quote={}]", "é".repeat(64))), vec![Part::Quote(Some("é".repeat(64)), true)]);
+  }
+
+  #[test]
+  fn test_length_with_code() {
+    assert_eq!(length(&parse("[code][b]hi[/b][/code]")), 9);
+  }
+
+  #[test]
+  fn test_to_markup_round_trip() {
+    let inputs = [
+      "[b]hello[/b] [i]world[/i] [:3]",
+      "[color=#A834cF]mixed case[/color]",
+      "\\[b]not bold\\[/b] and a trailing backslash \\",
+      "\\\\ \\\n [url]https://example.com[/url]",
+      "[[b]] [/color] [color=#zzzzzz] []",
+      "[url=example.com]text[/url] [url=] [url=[url=a]]",
+      "[code][b]\\[/code] [code]\n[/code] [code][code][/code][/code]",
+      "[codeblock] a\n[codeblock]\n[/codeblock][/codeblock]",
+      "[quote=lua]a [quote]b[/quote][/quote] [/quote] [quote=[b]]",
+      "[spoiler]hidden [b]text[/spoiler][/b] [/spoiler]",
+      "[sup]x[/sup][sub][small][/sub][/small] [/sup]",
+    ];
+    for input in inputs {
+      assert_eq!(to_markup(&parse(input)), input);
+    }
+  }
+
+  #[test]
+  fn test_escape_markup() {
+    assert_eq!(escape_markup("plain text"), "plain text");
+    assert_eq!(escape_markup("[b]not bold[/b] [:)]"), "[b\\]not bold[/b\\] [:)\\]");
+    assert_eq!(escape_markup("back\\slash [tags] ]"), "back\\\\slash [tags] ]");
+    assert_eq!(escape_markup("[[color=#ff0000]]"), "[[color=#ff0000\\]]");
+  }
+
+  #[test]
+  fn test_escape_parts() {
+    assert_eq!(
+      escape_parts("[:3]\nhi"),
+      vec![
+        Part::Text("[:3".to_string()),
+        Part::Escape,
+        Part::Text("]".to_string()),
+        Part::Newline,
+        Part::Text("hi".to_string())
+      ]
+    );
+  }
+
+  #[test]
+  fn test_check_well_formed() {
+    assert_eq!(check(&parse("[b]hello [i]world[/i][/b] [color=#ff0000]\\[b][/color] \\\\")), Ok(()));
+    assert_eq!(check(&parse("[url=example.com][b]x[/b][/url]")), Ok(()));
+    assert_eq!(check(&parse("[quote=lua][b]a[/b][quote]b[/quote][/quote]")), Ok(()));
+  }
+
+  #[test]
+  fn test_check_errors() {
+    assert_eq!(check(&parse("hello[/b]")), Err(StrictError::UnexpectedClose(1)));
+    assert_eq!(check(&parse("[b][i]x[/b][/i]")), Err(StrictError::Misnested(3)));
+    assert_eq!(check(&parse("[b][b]x[/b][/b]")), Err(StrictError::AlreadyOpen(1)));
+    assert_eq!(check(&parse("[url]x [color=#ffffff]y[/color]")), Err(StrictError::Unclosed(0)));
+    assert_eq!(check(&parse("[/color]")), Err(StrictError::UnexpectedClose(0)));
+    assert_eq!(check(&parse("oops \\")), Err(StrictError::TrailingEscape(1)));
+    assert_eq!(check(&parse("[url]a[url=b]c[/url][/url]")), Err(StrictError::AlreadyOpen(2)));
+    assert_eq!(check(&parse("[quote][b]a[/quote][/b]")), Err(StrictError::Misnested(3)));
+  }
+
+  #[test]
+  fn test_parse_strict() {
+    assert_eq!(parse_strict("[b]ok[/b]"), Ok(parse("[b]ok[/b]")));
+    assert!(parse_strict("[b]not ok").is_err());
+  }
+
+  /// collects the text content of parts, failing on anything other than text
+  fn literal_text(parts: &[Part]) -> Option<String> {
+    let mut text = String::new();
+    for part in parts {
+      match part {
+        Part::Text(t) => text.push_str(t),
+        Part::Newline => text.push('\n'),
+        Part::Escape => (),
+        _ => return None,
+      }
+    }
+    Some(text)
+  }
+
+  proptest! {
+
+    #[test]
+    fn prop_escape_markup_is_literal(input in "([\\[\\]\\\\/\n :;()#=+]|b|i|u|s|url|color|code|codeblock|quote|spoiler|sup|sub|small|D|B|3|[0-9a-fA-F]|é){0,64}") {
+      let parts = parse(&escape_markup(&input));
+      prop_assert_eq!(&parts, &escape_parts(&input));
+      prop_assert_eq!(literal_text(&parts), Some(input));
+    }
+
+    #[test]
+    fn prop_to_markup_round_trip_markup(input in "([\\[\\]\\\\/\n :;()#=+]|b|i|u|s|url|color|code|codeblock|quote|spoiler|sup|sub|small|D|B|3|[0-9a-fA-F]|é|🦊){0,64}") {
+      prop_assert_eq!(to_markup(&parse(&input)), input);
+    }
+
+    #[test]
+    fn prop_to_markup_round_trip_any(input in any::<String>()) {
+      prop_assert_eq!(to_markup(&parse(&input)), input);
+    }
+
+  }
+
 }
@@ -1,7 +1,9 @@
 use std::fmt::Write;
-use std::{rc::Rc, cell::RefCell};
+
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::parser::*;
+use crate::link::{autolink, deception, escape_href, LinkPolicy};
 
 /// escapes text so it can be safely used in HTML
 fn escape_html(text: &str) -> String {
@@ -19,172 +21,147 @@ fn escape_html(text: &str) -> String {
   result
 }
 
-/// escapes a URL by adding the http(s) protocol if it's not there
-fn escape_href(href: &str) -> String {
-  // trim the input
-  let href = href.trim();
-
-  // add the https protocol if it's not there (allows http too!)
-  if !href.starts_with("http://") && !href.starts_with("https://") {
-    format!("https://{}", href)
-  } else {
-    href.to_string()
-  }
+/// An element in the element stack of a [RenderEngine].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+  /// Bold text, from `[b]`.
+  Strong,
+  /// Italic text, from `[i]`.
+  Em,
+  /// Underlined text, from `[u]`.
+  Ins,
+  /// Strikethrough text, from `[s]`.
+  Del,
+  /// Spoiler, from `[spoiler]`.
+  Spoiler,
+  /// Superscript text, from `[sup]`.
+  Sup,
+  /// Subscript text, from `[sub]`.
+  Sub,
+  /// Small text, from `[small]`.
+  Small,
+  /// Colored text, from `[color=...]`.
+  Span { color: Color },
+  /// Link, from `[url]` or `[url=...]`, with the id used to look up its href.
+  /// Links never nest, opening a link closes the one that is already open.
+  A { id: usize },
 }
 
-/// represents a reference to the `href` field of a link in the outputted HTML
-#[derive(Debug, Clone)]
-struct Link(Rc<RefCell<Option<LinkData>>>);
+/// Output format driven by a [RenderEngine].
+///
+/// The engine resolves overlapping style toggles into a properly nested
+/// element stack, so a backend only ever sees elements opened and closed in
+/// a balanced order. At every line break, code block and quote boundary all
+/// open elements are closed before the backend is called and reopened after,
+/// except that a code block in a spoiler is written inside one.
+pub trait RenderBackend {
 
-/// represents the shared data of a [Link]
-#[derive(Debug)]
-struct LinkData {
-  href: String,
-  replacer: String,
-}
+  /// Opens an element.
+  fn open(&mut self, element: &Element);
 
-impl Link {
+  /// Closes an element, always the most recently opened one.
+  fn close(&mut self, element: &Element);
 
-  /// creates a new link with the given id
-  fn new(id: u32) -> Self {
-    let data = LinkData {
-      href: String::new(),
-      replacer: format!("§§HREF{id}§§"),
-    };
+  /// Writes text.
+  fn text(&mut self, text: &str);
 
-    Self(Rc::new(RefCell::new(Some(data))))
-  }
+  /// Writes an emoticon.
+  fn emote(&mut self, emote: EmoteKind);
 
-  /// appends text to the link's `href` field
-  fn append(&self, text: &str) {
-    let mut reference = self.0.borrow_mut();
-    let data = reference.as_mut().expect("link already taken");
-    data.href.push_str(text)
-  }
+  /// Starts a new line.
+  fn line_break(&mut self);
 
-  /// returns the replacer string of the link
-  fn replacer(&self) -> String {
-    let reference = self.0.borrow();
-    let data = reference.as_ref().expect("link already taken");
-    data.replacer.clone()
+  /// Writes inline code, which is written as text by default.
+  fn code(&mut self, code: &str) {
+    self.text(code);
   }
 
-  /// returns the link's data, removing it from the link
-  fn take(&self) -> LinkData {
-    self.0.take().expect("link already taken")
+  /// Writes a code block, called with no elements open except for a
+  /// [Element::Spoiler] if the block is in a spoiler. It is written as
+  /// text and line breaks by default.
+  fn code_block(&mut self, code: &str) {
+    for (index, line) in code.split('\n').enumerate() {
+      if index > 0 {
+        self.line_break();
+      }
+      if !line.is_empty() {
+        self.text(line);
+      }
+    }
   }
 
-}
+  /// Starts a quote block, with its author if it has one. Quotes nest, and
+  /// every quote is ended before [RenderBackend::finish]. Does nothing by default.
+  fn quote(&mut self, _author: Option<&str>) {}
 
-impl PartialEq<Link> for Link {
+  /// Ends the most recently started quote block. Does nothing by default.
+  fn end_quote(&mut self) {}
 
-  /// checks if two links are the same via pointer equality
-  fn eq(&self, other: &Link) -> bool {
-    Rc::ptr_eq(&self.0, &other.0)
-  }
+  /// Called with every escape, style, color, link and quote part where its tag appears,
+  /// before the element is opened or after it is closed. Does nothing by default.
+  fn markup(&mut self, _part: &Part) {}
 
-}
+  /// Called once after everything is closed, with the text collected by each
+  /// link, or its explicit href, indexed by [Element::A] id. Does nothing by default.
+  fn finish(&mut self, _links: &[String]) {}
 
-/// represents a HTML element in the element stack
-#[derive(Debug, Clone, PartialEq)]
-enum Element {
-    Strong,
-    Em,
-    Ins,
-    Del,
-    Span { color: Color },
-    A { link: Link },
 }
 
-/// renderer for sillycode markup
-#[derive(Default, Debug)]
-struct Renderer {
-  /// html output
-  html: String,
-
+/// Engine that resolves sillycode parts into calls to a [RenderBackend].
+#[derive(Debug)]
+pub struct RenderEngine<B: RenderBackend> {
+  /// output backend
+  backend: B,
   /// element stack
   elements: Vec<Element>,
-
-  /// counter for link ids
-  link_counter: u32,
-  /// list of all links
-  link_list: Vec<Link>,
-
-  /// whether the output is for an editor or not
-  is_editor: bool,
+  /// text of every link, indexed by link id
+  links: Vec<String>,
+  /// whether each link has an explicit href instead of collecting text
+  explicit: Vec<bool>,
+  /// number of open quotes
+  quotes: usize,
 }
 
-/// writes HTML to the renderer's buffer
-macro_rules! write_html {
-  ($self:ident, $($arg:tt)*) => {
-    write!(&mut $self.html, $($arg)*).unwrap()
-  }
-}
-
-/// writes "meta" text, usually tags like "[url]" or "[b]",
-/// wrapped in a span, to the renderer's buffer, if isEditor is true
-macro_rules! write_meta {
-  ($self:ident, $($arg:tt)*) => {
-    if $self.is_editor {
-      write_html!($self, "<span class=\"sillycode-meta\">");
-      write_html!($self, $($arg)*);
-      write_html!($self, "</span>");
-    }
-  };
-}
+impl<B: RenderBackend> RenderEngine<B> {
 
-impl Renderer {
+  /// Creates a new engine driving the given backend.
+  pub fn new(backend: B) -> Self {
+    Self { backend, elements: Vec::new(), links: Vec::new(), explicit: Vec::new(), quotes: 0 }
+  }
 
-  /// creates a new renderer
-  fn new() -> Self {
-    Self::default()
+  /// Returns the backend.
+  pub fn backend(&self) -> &B {
+    &self.backend
   }
 
-  /// opens an element
-  fn open(&mut self, element: &Element) {
-    match element {
-      Element::Strong => write_html!(self, "<strong>"),
-      Element::Em => write_html!(self, "<em>"),
-      Element::Ins => write_html!(self, "<ins>"),
-      Element::Del => write_html!(self, "<del>"),
-      Element::Span { color } => {
-        write_html!(self, "<span style=\"color: {color}\">");
-      }
-      Element::A { link } => {
-        write_html!(self, "<a href=\"{}\">", link.replacer());
-      }
-    }
+  /// Returns the current element stack, in the order the elements were opened.
+  pub fn elements(&self) -> &[Element] {
+    &self.elements
   }
 
-  /// closes an element
-  fn close(&mut self, element: &Element) {
-    match element {
-      Element::Strong => write_html!(self, "</strong>"),
-      Element::Em => write_html!(self, "</em>"),
-      Element::Ins => write_html!(self, "</ins>"),
-      Element::Del => write_html!(self, "</del>"),
-      Element::Span { color: _ } => write_html!(self, "</span>"),
-      Element::A { link: _ } => write_html!(self, "</a>"),
-    }
+  /// Returns the text collected so far by each link, or its explicit href,
+  /// indexed by [Element::A] id.
+  pub fn links(&self) -> &[String] {
+    &self.links
   }
 
   /// opens all elements in the element stack
   fn open_all(&mut self, elements: &[Element]) {
     for element in elements.iter() {
-      self.open(element);
+      self.backend.open(element);
     }
   }
 
   /// closes all elements in the element stack in reverse order
   fn close_all(&mut self, elements: &[Element]) {
     for element in elements.iter().rev() {
-      self.close(element);
+      self.backend.close(element);
     }
   }
 
   /// pushes an element onto the element stack
   fn push(&mut self, element: Element) {
-    self.open(&element);
+    self.backend.open(&element);
     self.elements.push(element);
   }
 
@@ -206,7 +183,7 @@ impl Renderer {
         self.close_all(&preserved);
 
         // close the removed element
-        self.close(&removed);
+        self.backend.close(&removed);
 
         // re-open all preserved elements
         self.open_all(&preserved);
@@ -230,41 +207,67 @@ impl Renderer {
     }
   }
 
-  /// creates a new link and adds it to the link list,
-  /// then pushes it to the element stack
-  fn push_link(&mut self) {
-    let link = Link::new(self.link_counter);
-    self.link_counter += 1;
-    self.link_list.push(link.clone());
-    self.push(Element::A { link });
+  /// creates a new link, with an explicit href or collecting its text,
+  /// and pushes it to the element stack
+  fn push_link(&mut self, href: Option<&str>) {
+    let id = self.links.len();
+    self.links.push(href.unwrap_or_default().to_string());
+    self.explicit.push(href.is_some());
+    self.push(Element::A { id });
   }
 
-  /// appends to all links in the element stack
+  /// closes the open link, if there is one
+  fn close_link(&mut self) {
+    self.remove(|e| matches!(e, Element::A { .. }));
+  }
+
+  /// appends to all links in the element stack without an explicit href
   fn append_link(&mut self, text: &str) {
     for element in self.elements.iter() {
-      if let Element::A { link } = element {
-        link.append(text);
+      if let Element::A { id } = element {
+        if !self.explicit[*id] {
+          self.links[*id].push_str(text);
+        }
       }
     }
   }
 
   /// handles text parts
   fn on_text(&mut self, text: &str) {
-    // escape the text for HTML
-    let text = escape_html(text);
-
-    // append the text to the HTML output
-    write_html!(self, "{text}");
+    // write the text
+    self.backend.text(text);
 
     // update the link hrefs
-    self.append_link(text.as_str());
+    self.append_link(text);
   }
 
-  /// handles escape parts
-  fn on_escape(&mut self) {
-    // emit the backslash if isEditor is true
-    // we don't need to do anything else! it's handled by the parser
-    write_meta!(self, "\\");
+  /// handles code parts
+  fn on_code(&mut self, code: &str) {
+    // write the code
+    self.backend.code(code);
+
+    // code is visible text too, so it goes into the link hrefs
+    self.append_link(code);
+  }
+
+  /// handles code block parts
+  fn on_code_block(&mut self, code: &str) {
+    // close all elements, a block can't be inside of them
+    let elements = self.elements.clone();
+    self.close_all(&elements);
+
+    // write the code block, still hidden if it is in a spoiler
+    let spoiler = self.contains(&Element::Spoiler);
+    if spoiler {
+      self.backend.open(&Element::Spoiler);
+    }
+    self.backend.code_block(code);
+    if spoiler {
+      self.backend.close(&Element::Spoiler);
+    }
+
+    // re-open all elements
+    self.open_all(&elements);
   }
 
   /// handles newline parts
@@ -275,8 +278,8 @@ impl Renderer {
     // close all elements used for styling to get back to the root of the tree
     self.close_all(&elements);
 
-    // close and open a new div to start a new line
-    write_html!(self, "</div><div>");
+    // start a new line
+    self.backend.line_break();
 
     // re-open all elements
     self.open_all(&elements);
@@ -284,94 +287,585 @@ impl Renderer {
 
   /// handles style parts
   fn on_style(&mut self, style: StyleKind, enable: bool) {
-    // links are a special case
-    if style == StyleKind::Link {
-      if enable {
-        write_meta!(self, "[url]");
-        self.push_link();
-      } else {
-        self.remove(|e| matches!(e, Element::A { .. }));
-        write_meta!(self, "[/url]");
-      }
-    // all other styles are handled by apply
-    } else {
-      if enable {
-        write_meta!(self, "[{}]", style.to_tag());
-      }
+    // links never nest, opening a link closes the one that is already open
+    if style == StyleKind::Link && enable {
+      self.close_link();
+    }
 
-      match style {
-          StyleKind::Bold => self.apply(Element::Strong, enable),
-          StyleKind::Italic => self.apply(Element::Em, enable),
-          StyleKind::Underline => self.apply(Element::Ins, enable),
-          StyleKind::Strikethrough => self.apply(Element::Del, enable),
-          _ => unreachable!(),
-      }
+    if enable {
+      self.backend.markup(&Part::Style(style, true));
+    }
 
-      if !enable {
-        write_meta!(self, "[/{}]", style.to_tag());
+    match style {
+      // links are a special case
+      StyleKind::Link => {
+        if enable {
+          self.push_link(None);
+        } else {
+          self.close_link();
+        }
       }
+      // all other styles are handled by apply
+      StyleKind::Bold => self.apply(Element::Strong, enable),
+      StyleKind::Italic => self.apply(Element::Em, enable),
+      StyleKind::Underline => self.apply(Element::Ins, enable),
+      StyleKind::Strikethrough => self.apply(Element::Del, enable),
+      StyleKind::Spoiler => self.apply(Element::Spoiler, enable),
+      StyleKind::Superscript => self.apply(Element::Sup, enable),
+      StyleKind::Subscript => self.apply(Element::Sub, enable),
+      StyleKind::Small => self.apply(Element::Small, enable),
+    }
+
+    if !enable {
+      self.backend.markup(&Part::Style(style, false));
     }
   }
 
+  /// handles link parts with an explicit href
+  fn on_link(&mut self, href: &str) {
+    self.close_link();
+    self.backend.markup(&Part::Link(href.to_string()));
+    self.push_link(Some(href));
+  }
+
   /// handles color parts
   fn on_color(&mut self, color: Color, enable: bool) {
     if enable {
-      write_meta!(self, "[color={color}]");
+      self.backend.markup(&Part::Color(color, true));
       self.push(Element::Span { color });
     } else {
       self.remove(|e| matches!(e, Element::Span { .. }));
-      write_meta!(self, "[/color]");
+      self.backend.markup(&Part::Color(color, false));
+    }
+  }
+
+  /// handles quote parts
+  fn on_quote(&mut self, author: Option<&str>, enable: bool) {
+    let part = Part::Quote(author.map(str::to_string), enable);
+
+    if enable {
+      self.backend.markup(&part);
+    }
+
+    // quotes are blocks, so all elements are closed around their boundaries,
+    // and closing tags without an open quote do nothing
+    if enable || self.quotes > 0 {
+      let elements = self.elements.clone();
+      self.close_all(&elements);
+
+      if enable {
+        self.quotes += 1;
+        self.backend.quote(author);
+      } else {
+        self.quotes -= 1;
+        self.backend.end_quote();
+      }
+
+      self.open_all(&elements);
+    }
+
+    if !enable {
+      self.backend.markup(&part);
+    }
+  }
+
+  /// Handles a single part.
+  pub fn part(&mut self, part: &Part) {
+    match part {
+      Part::Text(text) => self.on_text(text),
+      // escapes are handled by the parser, the backend may still show them
+      Part::Escape => self.backend.markup(part),
+      Part::Newline => self.on_newline(),
+      Part::Style(style, enable) => self.on_style(*style, *enable),
+      Part::Color(color, enable) => self.on_color(*color, *enable),
+      Part::Emote(emote) => self.backend.emote(*emote),
+      Part::Link(href) => self.on_link(href),
+      Part::Code(code) => self.on_code(code),
+      Part::CodeBlock(code) => self.on_code_block(code),
+      Part::Quote(author, enable) => self.on_quote(author.as_deref(), *enable),
+    }
+  }
+
+  /// Closes all open elements and quotes and returns the finished backend.
+  pub fn finish(mut self) -> B {
+    self.close_all(&self.elements.clone());
+    for _ in 0..self.quotes {
+      self.backend.end_quote();
+    }
+    self.backend.finish(&self.links);
+    self.backend
+  }
+
+  /// Handles all parts, then finishes and returns the backend.
+  pub fn render(mut self, parts: impl IntoIterator<Item = Part>) -> B {
+    for part in parts {
+      self.part(&part);
     }
+    self.finish()
+  }
+
+}
+
+/// HTML tag name and static attributes used for an element.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlTag {
+  /// Tag name like `"strong"`.
+  pub name: String,
+  /// Attributes added to every opening tag, as `(name, value)` pairs.
+  /// A `class` attribute is merged with the classes added by the renderer.
+  pub attributes: Vec<(String, String)>,
+}
+
+impl HtmlTag {
+
+  /// Creates a new tag without any attributes.
+  pub fn new(name: &str) -> Self {
+    Self { name: name.to_string(), attributes: Vec::new() }
   }
 
-  /// handles emote parts
-  fn on_emote(&mut self, emote: EmoteKind) {
+  /// Adds a static attribute to the tag.
+  pub fn with_attribute(mut self, name: &str, value: &str) -> Self {
+    self.attributes.push((name.to_string(), value.to_string()));
+    self
+  }
+
+  /// formats the opening tag, with the renderer's own attributes after the static ones
+  fn open(&self, dynamic: &[(&str, &str)]) -> String {
+    let mut html = format!("<{}", self.name);
+
+    for (name, value) in self.attributes.iter() {
+      if let Some((_, extra)) = dynamic.iter().find(|(n, _)| n == name) {
+        // merge classes, anything else is overridden by the renderer
+        if name == "class" {
+          write!(&mut html, " class=\"{} {extra}\"", escape_html(value)).unwrap();
+        }
+      } else {
+        write!(&mut html, " {}=\"{}\"", name, escape_html(value)).unwrap();
+      }
+    }
+
+    for (name, value) in dynamic.iter() {
+      if *name != "class" || !self.attributes.iter().any(|(n, _)| n == "class") {
+        write!(&mut html, " {name}=\"{value}\"").unwrap();
+      }
+    }
+
+    html.push('>');
+    html
+  }
+
+  /// formats the closing tag
+  fn close(&self) -> String {
+    format!("</{}>", self.name)
+  }
+
+}
+
+/// HTML tags used for each [Element], and for the lines of the output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementMap {
+  /// Tag for [Element::Strong], `<strong>` by default.
+  pub strong: HtmlTag,
+  /// Tag for [Element::Em], `<em>` by default.
+  pub em: HtmlTag,
+  /// Tag for [Element::Ins], `<ins>` by default.
+  pub ins: HtmlTag,
+  /// Tag for [Element::Del], `<del>` by default.
+  pub del: HtmlTag,
+  /// Tag for [Element::Span], `<span>` by default.
+  pub span: HtmlTag,
+  /// Tag for [Element::A], `<a>` by default.
+  pub a: HtmlTag,
+  /// Tag that each line is wrapped in, `<div>` by default.
+  pub line: HtmlTag,
+  /// Tag for inline code, `<code>` by default, also used inside code blocks.
+  pub code: HtmlTag,
+  /// Tag that code blocks are wrapped in, `<pre>` by default.
+  pub pre: HtmlTag,
+  /// Tag for spoilers, `<span class="sillycode-spoiler">` by default.
+  pub spoiler: HtmlTag,
+  /// Tag for superscript text, `<sup>` by default.
+  pub sup: HtmlTag,
+  /// Tag for subscript text, `<sub>` by default.
+  pub sub: HtmlTag,
+  /// Tag for small text, `<small>` by default.
+  pub small: HtmlTag,
+  /// Tag that quotes are wrapped in, `<blockquote>` by default.
+  pub blockquote: HtmlTag,
+  /// Tag for the author at the top of a quote, `<cite>` by default.
+  pub cite: HtmlTag,
+}
+
+impl Default for ElementMap {
+
+  /// Returns the default tags, `<strong>`, `<em>`, `<ins>`, `<del>`, `<span>`, `<a>`, `<div>`, `<code>`,
+  /// `<pre>`, `<span class="sillycode-spoiler">`, `<sup>`, `<sub>`, `<small>`, `<blockquote>` and `<cite>`.
+  fn default() -> Self {
+    Self {
+      strong: HtmlTag::new("strong"),
+      em: HtmlTag::new("em"),
+      ins: HtmlTag::new("ins"),
+      del: HtmlTag::new("del"),
+      span: HtmlTag::new("span"),
+      a: HtmlTag::new("a"),
+      line: HtmlTag::new("div"),
+      code: HtmlTag::new("code"),
+      pre: HtmlTag::new("pre"),
+      spoiler: HtmlTag::new("span").with_attribute("class", "sillycode-spoiler"),
+      sup: HtmlTag::new("sup"),
+      sub: HtmlTag::new("sub"),
+      small: HtmlTag::new("small"),
+      blockquote: HtmlTag::new("blockquote"),
+      cite: HtmlTag::new("cite"),
+    }
+  }
+
+}
+
+impl ElementMap {
+
+  /// Returns the tag used for an element.
+  pub fn get(&self, element: &Element) -> &HtmlTag {
+    match element {
+      Element::Strong => &self.strong,
+      Element::Em => &self.em,
+      Element::Ins => &self.ins,
+      Element::Del => &self.del,
+      Element::Spoiler => &self.spoiler,
+      Element::Sup => &self.sup,
+      Element::Sub => &self.sub,
+      Element::Small => &self.small,
+      Element::Span { .. } => &self.span,
+      Element::A { .. } => &self.a,
+    }
+  }
+
+}
+
+/// Options for [render_with].
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+  /// Whether to include visible markup tags for editing purposes.
+  pub is_editor: bool,
+  /// Whether to use CSS classes instead of inline `style` attributes, for
+  /// pages with a Content-Security-Policy that forbids inline styles.
+  /// Colors get classes like `sc-c-ad77f1` and editor emotes get classes like
+  /// `sillycode-emote-smile`, defined in [RenderOutput::stylesheet].
+  pub classes: bool,
+  /// HTML tags used for each element and line.
+  pub elements: ElementMap,
+  /// Policy applied to every link.
+  pub links: LinkPolicy,
+  /// Whether to turn bare URLs in text into links, see [autolink].
+  /// Has no effect in the editor, which only shows the tags that were typed.
+  pub autolink: bool,
+}
+
+/// Output of [render_with].
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct RenderOutput {
+  /// Rendered HTML.
+  pub html: String,
+  /// CSS rules for every class used in the HTML, empty unless
+  /// [RenderOptions::classes] is set.
+  pub stylesheet: String,
+}
+
+/// renderer backend for HTML output
+#[derive(Default, Debug)]
+struct HtmlRenderer {
+  /// html output
+  html: String,
+  /// css output
+  stylesheet: String,
+
+  /// colors used so far, when using classes
+  colors: Vec<Color>,
+  /// emotes used so far, when using classes
+  emotes: Vec<EmoteKind>,
+
+  /// visible text of every link, indexed by link id
+  link_texts: Vec<String>,
+  /// id of the link that is currently open
+  link: Option<usize>,
+  /// offsets in the html where link tags are filled in when finishing,
+  /// with the link id and whether the tag opens the link
+  link_tags: Vec<(usize, usize, bool)>,
+
+  /// index in the html where the current line starts
+  line: usize,
+  /// whether the current line has any content
+  content: bool,
+  /// whether the current line starts right after a quote
+  after_quote: bool,
+
+  /// render options
+  options: RenderOptions,
+}
+
+/// writes HTML to the renderer's buffer
+macro_rules! write_html {
+  ($self:ident, $($arg:tt)*) => {
+    write!(&mut $self.html, $($arg)*).unwrap()
+  }
+}
+
+/// writes "meta" text, usually tags like "[url]" or "[b]",
+/// wrapped in a span, to the renderer's buffer, if isEditor is true
+macro_rules! write_meta {
+  ($self:ident, $($arg:tt)*) => {
+    if $self.options.is_editor {
+      write_html!($self, "<span class=\"sillycode-meta\">");
+      write_html!($self, $($arg)*);
+      write_html!($self, "</span>");
+    }
+  };
+}
+
+impl HtmlRenderer {
+
+  /// creates a new renderer
+  fn new(options: &RenderOptions) -> Self {
+    let mut renderer = Self { options: options.clone(), ..Self::default() };
+    // start the output
+    renderer.start_line();
+    renderer
+  }
+
+  /// starts a new line
+  fn start_line(&mut self) {
+    self.line = self.html.len();
+    self.content = false;
+    write_html!(self, "{}", self.options.elements.line.open(&[]));
+  }
+
+  /// ends the current line, or drops it if it has no content and
+  /// `drop_empty` is set, since quotes already start a new block
+  fn end_line(&mut self, drop_empty: bool) {
+    if drop_empty && !self.content {
+      self.html.truncate(self.line);
+      self.link_tags.retain(|&(offset, _, _)| offset <= self.line);
+    } else {
+      write_html!(self, "{}", self.options.elements.line.close());
+    }
+  }
+
+  /// adds visible text to the link that is currently open
+  fn text_for_link(&mut self, text: &str) {
+    if let Some(id) = self.link {
+      if self.link_texts.len() <= id {
+        self.link_texts.resize(id + 1, String::new());
+      }
+      self.link_texts[id].push_str(text);
+    }
+  }
+
+}
+
+impl RenderBackend for HtmlRenderer {
+
+  /// opens an element
+  fn open(&mut self, element: &Element) {
+    let tag = self.options.elements.get(element);
+    let html = match element {
+      Element::Span { color } => {
+        // always lowercase, whatever case the markup used
+        let hex = format!("{:02x}{:02x}{:02x}", color.r, color.g, color.b);
+        if self.options.classes {
+          // one rule per RGB value, whatever case the markup used
+          let rgb = Color::new(color.r, color.g, color.b);
+          if !self.colors.contains(&rgb) {
+            self.colors.push(rgb);
+            writeln!(&mut self.stylesheet, ".sc-c-{hex} {{ color: #{hex}; }}").unwrap();
+          }
+          tag.open(&[("class", &format!("sc-c-{hex}"))])
+        } else {
+          tag.open(&[("style", &format!("color: #{hex}"))])
+        }
+      }
+      // the link is filled in when finishing, once its href is known
+      Element::A { id } => {
+        self.link = Some(*id);
+        self.link_tags.push((self.html.len(), *id, true));
+        String::new()
+      }
+      _ => tag.open(&[]),
+    };
+    write_html!(self, "{html}");
+  }
+
+  /// closes an element
+  fn close(&mut self, element: &Element) {
+    let html = match element {
+      Element::A { id } => {
+        self.link = None;
+        self.link_tags.push((self.html.len(), *id, false));
+        String::new()
+      }
+      _ => self.options.elements.get(element).close(),
+    };
+    write_html!(self, "{html}");
+  }
+
+  /// writes text, escaped for HTML
+  fn text(&mut self, text: &str) {
+    write_html!(self, "{}", escape_html(text));
+    self.text_for_link(text);
+    self.content |= !text.is_empty();
+  }
+
+  /// writes an emote image
+  fn emote(&mut self, emote: EmoteKind) {
     let tag = emote.to_tag();
     let name = emote.to_name();
     let path = format!("/static/emoticons/{}.png", name);
-    if self.is_editor {
+    self.content = true;
+    if self.options.is_editor && self.options.classes {
+      if !self.emotes.contains(&emote) {
+        self.emotes.push(emote);
+        writeln!(&mut self.stylesheet, ".sillycode-emote-{name} {{ background-image: url({path}); }}").unwrap();
+      }
+      write_html!(self, "<span class=\"sillycode-emote sillycode-emote-{name}\">[{tag}]</span>");
+    } else if self.options.is_editor {
       write_html!(self, "<span class=\"sillycode-emote\" style=\"background-image: url({path})\">[{tag}]</span>");
     } else {
       write_html!(self, "<img class=\"sillycode-emote\" src=\"{path}\" alt=\"{name}\">");
     }
   }
 
-  /// renders a bunch of parts as HTML
-  fn render(mut self, parts: impl IntoIterator<Item = Part>) -> String {
-    // start the output
-    write_html!(self, "<div>");
+  /// writes inline code, escaped for HTML
+  fn code(&mut self, code: &str) {
+    self.text_for_link(code);
+    self.content = true;
+    let tag = &self.options.elements.code;
+    let html = format!("{}{}{}", tag.open(&[]), escape_html(code), tag.close());
+    write_meta!(self, "[code]");
+    write_html!(self, "{html}");
+    write_meta!(self, "[/code]");
+  }
 
-    // render the parts
-    for part in parts {
-      match part {
-        Part::Text(text) => self.on_text(&text),
-        Part::Escape => self.on_escape(),
-        Part::Newline => self.on_newline(),
-        Part::Style(style, enable) => self.on_style(style, enable),
-        Part::Color(color, enable) => self.on_color(color, enable),
-        Part::Emote(emote) => self.on_emote(emote),
-      }
+  /// writes a code block inside the current line, escaped for HTML, its
+  /// whitespace is kept as is since it never touches the line tags
+  fn code_block(&mut self, code: &str) {
+    let (pre, tag) = (&self.options.elements.pre, &self.options.elements.code);
+    let html = format!("{}{}{}{}{}", pre.open(&[]), tag.open(&[]), escape_html(code), tag.close(), pre.close());
+    self.content = true;
+    write_meta!(self, "[codeblock]");
+    write_html!(self, "{html}");
+    write_meta!(self, "[/codeblock]");
+  }
+
+  /// ends the current line and starts a quote with a new line inside it,
+  /// the editor only shows the tags so that every line stays a direct child
+  fn quote(&mut self, author: Option<&str>) {
+    if self.options.is_editor {
+      return;
     }
+    self.end_line(true);
+    let elements = &self.options.elements;
+    let mut html = elements.blockquote.open(&[]);
+    if let Some(author) = author {
+      write!(&mut html, "{}{}{}", elements.cite.open(&[]), escape_html(author), elements.cite.close()).unwrap();
+    }
+    write_html!(self, "{html}");
+    self.start_line();
+    self.after_quote = false;
+  }
 
-    // close all elements
-    self.close_all(&self.elements.clone());
+  /// ends the current line and the quote, and starts a new line after it
+  fn end_quote(&mut self) {
+    if self.options.is_editor {
+      return;
+    }
+    self.end_line(true);
+    write_html!(self, "{}", self.options.elements.blockquote.close());
+    self.start_line();
+    self.after_quote = true;
+  }
+
+  /// closes and opens a new div to start a new line, a line break right
+  /// after a quote only ends the line the quote started
+  fn line_break(&mut self) {
+    self.end_line(self.after_quote);
+    self.start_line();
+    self.after_quote = false;
+  }
+
+  /// writes tags as meta text if isEditor is true
+  fn markup(&mut self, part: &Part) {
+    write_meta!(self, "{}", escape_html(&part.to_string()));
+  }
+
+  /// closes the output and fills in the link hrefs
+  fn finish(&mut self, links: &[String]) {
+    let open = self.options.elements.line.open(&[]);
+    let close = self.options.elements.line.close();
 
     // close the output
-    write_html!(self, "</div>");
+    self.end_line(self.after_quote);
+
+    // build the tags of every link, or nothing if not allowed
+    let policy = &self.options.links;
+    let tag = &self.options.elements.a;
+    let mut tags = Vec::new();
+    for (id, text) in links.iter().enumerate() {
+      let (open_tag, close_tag) = match policy.resolve(text) {
+        Some(href) => {
+          let href = escape_html(&href);
+          let rel = policy.rel.as_deref().map(escape_html);
+          let mut attributes = vec![("href", href.as_str())];
+          if let Some(rel) = &rel {
+            attributes.push(("rel", rel));
+          }
+          if policy.new_tab {
+            attributes.push(("target", "_blank"));
+          }
+          (tag.open(&attributes), tag.close())
+        }
+        None => (String::new(), String::new()),
+      };
+
+      // show the real host after the last part of deceptive links
+      let mut label = String::new();
+      if policy.show_deceptive_hosts && !self.options.is_editor && policy.resolve(text).is_some() {
+        let visible = self.link_texts.get(id).map(String::as_str).unwrap_or_default();
+        if let Some(deceptive) = escape_href(text).and_then(|href| deception(&href, visible)) {
+          label = format!("<span class=\"sillycode-link-host\">({})</span>", escape_html(deceptive.host()));
+        }
+      }
+      tags.push((open_tag, close_tag, label));
+    }
 
-    // replace all link references with the actual hrefs
-    for link in self.link_list.iter().map(|link| link.take()) {
-      self.html = self.html.replace(&link.replacer, &escape_href(&link.href));
+    // fill in the tags where the links were opened and closed
+    let mut last_close = vec![None; tags.len()];
+    for (index, &(_, id, opens)) in self.link_tags.iter().enumerate() {
+      if !opens && id < tags.len() {
+        last_close[id] = Some(index);
+      }
     }
+    let mut html = String::with_capacity(self.html.len());
+    let mut written = 0;
+    for (index, &(offset, id, opens)) in self.link_tags.iter().enumerate() {
+      html.push_str(&self.html[written..offset]);
+      written = offset;
+      let Some((open_tag, close_tag, label)) = tags.get(id) else { continue };
+      if opens {
+        html.push_str(open_tag);
+      } else {
+        html.push_str(close_tag);
+        if last_close[id] == Some(index) {
+          html.push_str(label);
+        }
+      }
+    }
+    html.push_str(&self.html[written..]);
+    self.html = html;
 
     // postprocess the html to add <br> tags where needed
     self.html = self.html
-      .replace("<div> ", "<div>&nbsp;")
-      .replace(" </div>", " <br></div>")
-      .replace("<div></div>", "<div><br></div>");
-
-    // we are done :3
-    self.html
+      .replace(&format!("{open} "), &format!("{open}&nbsp;"))
+      .replace(&format!(" {close}"), &format!(" <br>{close}"))
+      .replace(&format!("{open}{close}"), &format!("{open}<br>{close}"));
   }
 
 }
@@ -380,7 +874,137 @@ impl Renderer {
 ///
 /// Set `is_editor` to `true` to include visible markup tags for editing purposes.
 pub fn render(parts: impl IntoIterator<Item = Part>, is_editor: bool) -> String {
-  let mut renderer = Renderer::new();
-  renderer.is_editor = is_editor;
-  renderer.render(parts)
+  render_with(parts, &RenderOptions { is_editor, ..RenderOptions::default() }).html
+}
+
+/// Renders parsed sillycode parts as HTML using the given options.
+pub fn render_with(parts: impl IntoIterator<Item = Part>, options: &RenderOptions) -> RenderOutput {
+  let engine = RenderEngine::new(HtmlRenderer::new(options));
+  let renderer = if options.autolink && !options.is_editor {
+    engine.render(autolink(&parts.into_iter().collect::<Vec<_>>()))
+  } else {
+    engine.render(parts)
+  };
+  // we are done :3
+  RenderOutput { html: renderer.html, stylesheet: renderer.stylesheet }
+}
+
+/// renderer backend for plain text output
+#[derive(Default, Debug)]
+struct PlainRenderer {
+  /// text output
+  text: String,
+  /// number of open quotes, each line inside them starts with a "> " for each
+  quotes: usize,
+  /// byte offset in the text where the current line starts, before its "> " prefixes
+  line: usize,
+  /// whether the current line has any content after its "> " prefixes
+  in_line: bool,
+  /// whether a quote ended on the current line, so the next content goes on a new line
+  pending: bool,
+  /// whether the content is in a spoiler, which is masked
+  spoiler: bool,
+}
+
+impl PlainRenderer {
+
+  /// starts the new line that is pending after a quote
+  fn flush(&mut self) {
+    if self.pending {
+      self.line_break();
+    }
+  }
+
+}
+
+impl RenderBackend for PlainRenderer {
+
+  /// plain text has no elements, only spoilers are tracked
+  fn open(&mut self, element: &Element) {
+    if *element == Element::Spoiler {
+      self.spoiler = true;
+    }
+  }
+
+  /// plain text has no elements, only spoilers are tracked
+  fn close(&mut self, element: &Element) {
+    if *element == Element::Spoiler {
+      self.spoiler = false;
+    }
+  }
+
+  /// writes text as is, or with every grapheme but whitespace masked in spoilers
+  fn text(&mut self, text: &str) {
+    if !text.is_empty() {
+      self.flush();
+      if self.spoiler {
+        for grapheme in text.graphemes(true) {
+          self.text.push_str(if grapheme.trim().is_empty() { grapheme } else { "█" });
+        }
+      } else {
+        self.text.push_str(text);
+      }
+      self.in_line = true;
+    }
+  }
+
+  /// writes an emote as its tag, or masked in spoilers
+  fn emote(&mut self, emote: EmoteKind) {
+    self.flush();
+    if self.spoiler {
+      self.text.push('█');
+    } else {
+      write!(&mut self.text, "[{}]", emote.to_tag()).unwrap();
+    }
+    self.in_line = true;
+  }
+
+  /// writes a newline, followed by the prefixes of the open quotes
+  fn line_break(&mut self) {
+    self.text.push('\n');
+    self.line = self.text.len();
+    self.text.push_str(&"> ".repeat(self.quotes));
+    self.in_line = false;
+    self.pending = false;
+  }
+
+  /// starts a quote on its own line, with a line for the author
+  fn quote(&mut self, author: Option<&str>) {
+    if self.in_line {
+      self.line_break();
+    }
+    self.quotes += 1;
+    self.text.push_str("> ");
+    if let Some(author) = author {
+      write!(&mut self.text, "{author}:").unwrap();
+      self.line_break();
+    }
+  }
+
+  /// ends a quote, the next content continues on a line of its own
+  fn end_quote(&mut self) {
+    self.quotes -= 1;
+    if !self.in_line {
+      // the current line is still empty, so it is dropped like the html renderer does
+      self.text.truncate(self.line);
+      if self.text.pop().is_some() {
+        // back on the previous line, which ends with content
+        self.line = self.text.rfind('\n').map_or(0, |index| index + 1);
+        self.in_line = true;
+      } else {
+        // the first line stays, as the empty line of the quotes still open
+        self.text.push_str(&"> ".repeat(self.quotes));
+      }
+    }
+    self.pending = self.in_line;
+  }
+
+}
+
+/// Renders parsed sillycode parts as plain text, without any formatting.
+///
+/// Emotes are written as their tags like `[:)]`, and everything in a
+/// spoiler except whitespace is masked with `█`.
+pub fn render_plain(parts: impl IntoIterator<Item = Part>) -> String {
+  RenderEngine::new(PlainRenderer::default()).render(parts).text
 }
@@ -2,8 +2,12 @@
 #[cfg(test)]
 mod tests {
 
+  use std::sync::Arc;
+
   use crate::parser::*;
   use crate::renderer::*;
+  use crate::link::*;
+  use proptest::prelude::*;
 
   #[test]
   fn test_render_nothing() {
@@ -32,6 +36,14 @@ mod tests {
       "<div><span style=\"color: #ff0000\">this text is red</span></div>");
   }
 
+  #[test]
+  fn test_render_colored_text_is_lowercase() {
+    assert_eq!(render(parse("[color=#FF00aA]pink[/color]"), false),
+      "<div><span style=\"color: #ff00aa\">pink</span></div>");
+    assert_eq!(render(parse("[color=#FF00aA]pink[/color]"), true),
+      "<div><span class=\"sillycode-meta\">[color=#FF00aA]</span><span style=\"color: #ff00aa\">pink</span><span class=\"sillycode-meta\">[/color]</span></div>");
+  }
+
   #[test]
   fn test_render_link() {
     assert_eq!(render(parse("check this out: [url]https://example.com[/url]"), false),
@@ -46,20 +58,56 @@ mod tests {
 
   #[test]
   fn test_render_link_with_styles_inside() {
+    assert_eq!(render(parse("[url]example.com/[b]bold[/b]![/url]"), false),
+      "<div><a href=\"https://example.com/bold!\">example.com/<strong>bold</strong>!</a></div>");
+  }
+
+  #[test]
+  fn test_render_invalid_link_as_text() {
     assert_eq!(render(parse("[url][b]bold[/b]! wow![/url]"), false),
-      "<div><a href=\"https://bold! wow!\"><strong>bold</strong>! wow!</a></div>");
+      "<div><strong>bold</strong>! wow!</div>");
+  }
+
+  #[test]
+  fn test_render_link_encoding() {
+    assert_eq!(render(parse("[url]example.com/a b?q=\"x\"[/url] [url]http://bücher.de[/url]"), false),
+      "<div><a href=\"https://example.com/a%20b?q=%22x%22\">example.com/a b?q=&quot;x&quot;</a> <a href=\"http://xn--bcher-kva.de\">http://bücher.de</a></div>");
   }
 
   #[test]
   fn test_render_link_ignores_emote() {
     assert_eq!(render(parse("[url]face: [:(][/url]"), false),
-      "<div><a href=\"https://face:\">face: <img class=\"sillycode-emote\" src=\"/static/emoticons/sad.png\" alt=\"sad\"></a></div>");
+      "<div><a href=\"https://face\">face: <img class=\"sillycode-emote\" src=\"/static/emoticons/sad.png\" alt=\"sad\"></a></div>");
+  }
+
+  #[test]
+  fn test_render_link_with_href() {
+    assert_eq!(render(parse("[url=example.com/\"x\"]click [b]here[/b][/url]"), false),
+      "<div><a href=\"https://example.com/%22x%22\">click <strong>here</strong></a></div>");
+    assert_eq!(render(parse("[url=example.com]click[/url]"), true),
+      "<div><span class=\"sillycode-meta\">[url=example.com]</span><a href=\"https://example.com\">click</a><span class=\"sillycode-meta\">[/url]</span></div>");
+    assert_eq!(render(parse("[url=<b>]x[/url]"), true),
+      "<div><span class=\"sillycode-meta\">[url=&lt;b&gt;]</span>x<span class=\"sillycode-meta\">[/url]</span></div>");
+  }
+
+  #[test]
+  fn test_render_link_placeholders_in_text() {
+    assert_eq!(render(parse("§§A0§§ hi [url]example.com[/url] §§/A0§§"), false),
+      "<div>§§A0§§ hi <a href=\"https://example.com\">example.com</a> §§/A0§§</div>");
   }
 
   #[test]
   fn test_render_nested_links() {
     assert_eq!(render(parse("[url]this is a link: [url]https://example.com[/url][/url]"), false),
-      "<div><a href=\"https://this is a link: https://example.com\">this is a link: <a href=\"https://example.com\">https://example.com</a></a></div>");
+      "<div>this is a link: <a href=\"https://example.com\">https://example.com</a></div>");
+    assert_eq!(render(parse("[url]example.org/[b]x[url=example.com]y[/b]z[/url] after[/url]"), false),
+      "<div><a href=\"https://example.org/x\">example.org/<strong>x</strong></a><strong><a href=\"https://example.com\">y</a></strong><a href=\"https://example.com\">z</a> after</div>");
+  }
+
+  #[test]
+  fn test_render_nested_links_in_editor() {
+    assert_eq!(render(parse("[url]a.com[url]b.com[/url][/url]"), true),
+      "<div><span class=\"sillycode-meta\">[url]</span><a href=\"https://a.com\">a.com</a><span class=\"sillycode-meta\">[url]</span><a href=\"https://b.com\">b.com</a><span class=\"sillycode-meta\">[/url]</span><span class=\"sillycode-meta\">[/url]</span></div>");
   }
 
   #[test]
@@ -88,8 +136,8 @@ mod tests {
 
   #[test]
   fn test_render_link_spans_multiple_lines() {
-    assert_eq!(render(parse("[url]https://example.com\nthis is a link[/url] teehee"), false),
-      "<div><a href=\"https://example.comthis is a link\">https://example.com</a></div><div><a href=\"https://example.comthis is a link\">this is a link</a> teehee</div>");
+    assert_eq!(render(parse("[url]https://example.com/\nlink[/url] teehee"), false),
+      "<div><a href=\"https://example.com/link\">https://example.com/</a></div><div><a href=\"https://example.com/link\">link</a> teehee</div>");
   }
 
   #[test]
@@ -125,13 +173,13 @@ mod tests {
   #[test]
   fn test_render_even_more_evil_html() {
     assert_eq!(render(parse("please let me [url]<script>alert('hello')</script>[/url] smuggle \\<iframe src='https://example.com'\\>\\</iframe\\> something in"), false),
-      "<div>please let me <a href=\"https://&lt;script&gt;alert(&#39;hello&#39;)&lt;/script&gt;\">&lt;script&gt;alert(&#39;hello&#39;)&lt;/script&gt;</a> smuggle &lt;iframe src=&#39;https://example.com&#39;&gt;&lt;/iframe&gt; something in</div>");
+      "<div>please let me &lt;script&gt;alert(&#39;hello&#39;)&lt;/script&gt; smuggle &lt;iframe src=&#39;https://example.com&#39;&gt;&lt;/iframe&gt; something in</div>");
   }
 
   #[test]
   fn test_render_evil_link() {
     assert_eq!(render(parse("[url]javascript:fetch('/css/lua').then(r=>r.text()).then(eval)[/url]"), false),
-      "<div><a href=\"https://javascript:fetch(&#39;/css/lua&#39;).then(r=&gt;r.text()).then(eval)\">javascript:fetch(&#39;/css/lua&#39;).then(r=&gt;r.text()).then(eval)</a></div>");
+      "<div>javascript:fetch(&#39;/css/lua&#39;).then(r=&gt;r.text()).then(eval)</div>");
   }
 
   #[test]
@@ -170,6 +218,36 @@ mod tests {
       "<div>this <span class=\"sillycode-meta\">[b]</span><strong>text</strong><span class=\"sillycode-meta\">[/b]</span> has <span class=\"sillycode-meta\">[i]</span><em>markup rendered</em><span class=\"sillycode-meta\">[/i]</span> and <span class=\"sillycode-meta\">\\</span>[url] all that <span class=\"sillycode-meta\">\\</span></div>");
   }
 
+  #[test]
+  fn test_render_spoiler() {
+    assert_eq!(
+      render(parse("[spoiler]a [b]b[/spoiler]c[/b]"), false),
+      "<div><span class=\"sillycode-spoiler\">a <strong>b</strong></span><strong>c</strong></div>"
+    );
+    assert_eq!(
+      render(parse("[spoiler]x[/spoiler]"), true),
+      "<div><span class=\"sillycode-meta\">[spoiler]</span><span class=\"sillycode-spoiler\">x</span><span class=\"sillycode-meta\">[/spoiler]</span></div>"
+    );
+  }
+
+  #[test]
+  fn test_render_sup_sub_small() {
+    assert_eq!(
+      render(parse("x[sup]2[/sup] H[sub]2[/sub]O [small]fine [b]print[/small]![/b]"), false),
+      "<div>x<sup>2</sup> H<sub>2</sub>O <small>fine <strong>print</strong></small><strong>!</strong></div>"
+    );
+    assert_eq!(
+      render(parse("[small]x[/small]"), true),
+      "<div><span class=\"sillycode-meta\">[small]</span><small>x</small><span class=\"sillycode-meta\">[/small]</span></div>"
+    );
+  }
+
+  #[test]
+  fn test_render_plain_spoiler() {
+    assert_eq!(render_plain(parse("it was [spoiler]the 🦊 [:3][/spoiler] all along")), "it was ███ █ █ all along");
+    assert_eq!(render_plain(parse("[spoiler]a\n[code]b[/code][/spoiler][spoiler]c")), "█\n██");
+  }
+
   #[test]
   fn test_render_show_meta_with_multiple_lines() {
     assert_eq!(render(parse("this [b]text\nhas[/b] [i]markup rendered[/i]"), true),
@@ -182,4 +260,276 @@ mod tests {
       "<div>this text has an emote <span class=\"sillycode-emote\" style=\"background-image: url(/static/emoticons/colonthree.png)\">[:3]</span></div>");
   }
 
+  #[test]
+  fn test_render_with_classes() {
+    let options = RenderOptions { classes: true, ..RenderOptions::default() };
+    let output = render_with(parse("[color=#FF0000]red [color=#00ff00]green[/color]\nred again[/color] [:3]"), &options);
+    assert_eq!(output.html,
+      "<div><span class=\"sc-c-ff0000\">red <span class=\"sc-c-00ff00\">green</span></span></div><div><span class=\"sc-c-ff0000\">red again</span> <img class=\"sillycode-emote\" src=\"/static/emoticons/colonthree.png\" alt=\"colonthree\"></div>");
+    assert_eq!(output.stylesheet,
+      ".sc-c-ff0000 { color: #ff0000; }\n.sc-c-00ff00 { color: #00ff00; }\n");
+    let output = render_with(parse("[color=#FF0000]a[/color][color=#ff0000]b[/color]"), &options);
+    assert_eq!(output.stylesheet, ".sc-c-ff0000 { color: #ff0000; }\n");
+  }
+
+  #[test]
+  fn test_render_with_classes_in_editor() {
+    let options = RenderOptions { is_editor: true, classes: true, ..RenderOptions::default() };
+    let output = render_with(parse("[:3] [:3] [:)]"), &options);
+    assert_eq!(output.html,
+      "<div><span class=\"sillycode-emote sillycode-emote-colonthree\">[:3]</span> <span class=\"sillycode-emote sillycode-emote-colonthree\">[:3]</span> <span class=\"sillycode-emote sillycode-emote-smile\">[:)]</span></div>");
+    assert_eq!(output.stylesheet,
+      ".sillycode-emote-colonthree { background-image: url(/static/emoticons/colonthree.png); }\n.sillycode-emote-smile { background-image: url(/static/emoticons/smile.png); }\n");
+  }
+
+  #[test]
+  fn test_render_with_element_map() {
+    let elements = ElementMap {
+      strong: HtmlTag::new("b"),
+      em: HtmlTag::new("i"),
+      ins: HtmlTag::new("u"),
+      del: HtmlTag::new("s").with_attribute("data-kind", "strike"),
+      line: HtmlTag::new("p").with_attribute("class", "line"),
+      ..ElementMap::default()
+    };
+    let options = RenderOptions { elements, ..RenderOptions::default() };
+    assert_eq!(render_with(parse("[b]bold[/b] [i]it[u]al[s]ic[/s][/u][/i]\n\n trailing "), &options).html,
+      "<p class=\"line\"><b>bold</b> <i>it<u>al<s data-kind=\"strike\">ic</s></u></i></p><p class=\"line\"><br></p><p class=\"line\">&nbsp;trailing <br></p>");
+  }
+
+  #[test]
+  fn test_render_with_element_map_merges_classes() {
+    let elements = ElementMap {
+      span: HtmlTag::new("span").with_attribute("class", "colored").with_attribute("data-x", "\"quoted\""),
+      a: HtmlTag::new("a").with_attribute("class", "link").with_attribute("href", "ignored"),
+      ..ElementMap::default()
+    };
+    let options = RenderOptions { classes: true, elements, ..RenderOptions::default() };
+    assert_eq!(render_with(parse("[color=#ff0000][url]example.com[/url][/color]"), &options).html,
+      "<div><span class=\"colored sc-c-ff0000\" data-x=\"&quot;quoted&quot;\"><a class=\"link\" href=\"https://example.com\">example.com</a></span></div>");
+  }
+
+  #[test]
+  fn test_render_with_link_policy() {
+    let options = RenderOptions {
+      links: LinkPolicy { new_tab: true, ..LinkPolicy::user_content() },
+      ..RenderOptions::default()
+    };
+    assert_eq!(render_with(parse("[url]example.com[/url]"), &options).html,
+      "<div><a href=\"https://example.com\" rel=\"nofollow ugc noopener\" target=\"_blank\">example.com</a></div>");
+  }
+
+  #[test]
+  fn test_render_with_link_policy_disallowed() {
+    let options = RenderOptions {
+      links: LinkPolicy { deny_hosts: vec!["evil.com".to_string()], ..LinkPolicy::default() },
+      ..RenderOptions::default()
+    };
+    assert_eq!(render_with(parse("[url]ev[b]il.com[/url] ok[/b] [url]good.com[/url]"), &options).html,
+      "<div>ev<strong>il.com</strong><strong> ok</strong> <a href=\"https://good.com\">good.com</a></div>");
+
+    let options = RenderOptions { is_editor: true, ..options };
+    assert_eq!(render_with(parse("[url]evil\n.com[/url]"), &options).html,
+      "<div><span class=\"sillycode-meta\">[url]</span>evil</div><div>.com<span class=\"sillycode-meta\">[/url]</span></div>");
+  }
+
+  #[test]
+  fn test_render_with_link_policy_rewrite() {
+    let options = RenderOptions {
+      links: LinkPolicy { rewrite: Some(Arc::new(|href| format!("/out?u={href}"))), ..LinkPolicy::default() },
+      ..RenderOptions::default()
+    };
+    assert_eq!(render_with(parse("[url]example.com/?a=1&b=2[/url]"), &options).html,
+      "<div><a href=\"/out?u=https://example.com/?a=1&amp;b=2\">example.com/?a=1&amp;b=2</a></div>");
+  }
+
+  #[test]
+  fn test_render_with_autolink() {
+    let options = RenderOptions {
+      autolink: true,
+      links: LinkPolicy { deny_hosts: vec!["evil.com".to_string()], ..LinkPolicy::user_content() },
+      ..RenderOptions::default()
+    };
+    assert_eq!(render_with(parse("visit www.example.com, or www.evil.com!"), &options).html,
+      "<div>visit <a href=\"https://www.example.com\" rel=\"nofollow ugc noopener\">www.example.com</a>, or www.evil.com!</div>");
+    assert_eq!(render(parse("visit www.example.com"), false), "<div>visit www.example.com</div>");
+  }
+
+  #[test]
+  fn test_render_with_autolink_in_editor() {
+    let options = RenderOptions { autolink: true, is_editor: true, ..RenderOptions::default() };
+    assert_eq!(render_with(parse("visit https://a.com"), &options).html, "<div>visit https://a.com</div>");
+  }
+
+  #[test]
+  fn test_render_with_deceptive_hosts() {
+    let options = RenderOptions {
+      links: LinkPolicy { show_deceptive_hosts: true, ..LinkPolicy::default() },
+      ..RenderOptions::default()
+    };
+    assert_eq!(render_with(parse("[url=evil.com]paypal.com/\n[b]login[/b][/url] [url]example.com[/url]"), &options).html,
+      "<div><a href=\"https://evil.com\">paypal.com/</a></div><div><a href=\"https://evil.com\"><strong>login</strong></a><span class=\"sillycode-link-host\">(evil.com)</span> <a href=\"https://example.com\">example.com</a></div>");
+
+    let options = RenderOptions { is_editor: true, ..options };
+    assert!(!render_with(parse("[url=evil.com]paypal.com[/url]"), &options).html.contains("sillycode-link-host"));
+  }
+
+  #[test]
+  fn test_render_with_defaults() {
+    let output = render_with(parse("[color=#ff0000]red[/color]"), &RenderOptions::default());
+    assert_eq!(output.html, render(parse("[color=#ff0000]red[/color]"), false));
+    assert_eq!(output.stylesheet, "");
+  }
+
+  #[test]
+  fn test_render_code() {
+    assert_eq!(render(parse("[code]<b>[:3][/code]"), false), "<div><code>&lt;b&gt;[:3]</code></div>");
+    assert_eq!(
+      render(parse("[code]x[/code]"), true),
+      "<div><span class=\"sillycode-meta\">[code]</span><code>x</code><span class=\"sillycode-meta\">[/code]</span></div>"
+    );
+    assert_eq!(
+      render(parse("[url][code]example.com[/code][/url]"), false),
+      "<div><a href=\"https://example.com\"><code>example.com</code></a></div>"
+    );
+    assert_eq!(render_plain(parse("[code][b]x[/b][/code]")), "[b]x[/b]");
+  }
+
+  #[test]
+  fn test_render_code_block() {
+    assert_eq!(
+      render(parse("[b]a [codeblock]  x [b]\n\n  <y> [/codeblock] b[/b]"), false),
+      "<div><strong>a </strong><pre><code>  x [b]\n\n  &lt;y&gt; </code></pre><strong> b</strong></div>"
+    );
+    assert_eq!(
+      render(parse("[codeblock] x [/codeblock]\nnext"), true),
+      "<div><span class=\"sillycode-meta\">[codeblock]</span><pre><code> x </code></pre><span class=\"sillycode-meta\">[/codeblock]</span></div><div>next</div>"
+    );
+    assert_eq!(render_plain(parse("a\n[codeblock]x\n[:3][/codeblock]")), "a\nx\n[:3]");
+  }
+
+  #[test]
+  fn test_render_code_block_in_spoiler() {
+    let parts = parse("[spoiler]the killer is [b][codeblock]Bob[/codeblock][/b][/spoiler]");
+    assert_eq!(
+      render(parts.clone(), false),
+      concat!(
+        "<div><span class=\"sillycode-spoiler\">the killer is <strong></strong></span>",
+        "<span class=\"sillycode-spoiler\"><pre><code>Bob</code></pre></span>",
+        "<span class=\"sillycode-spoiler\"><strong></strong></span></div>",
+      )
+    );
+    assert_eq!(render_plain(parts), "███ ██████ ██ ███");
+    assert_eq!(render_plain(parse("[spoiler][codeblock]a b\nc[/codeblock][/spoiler]\nd")), "█ █\n█\nd");
+  }
+
+  #[test]
+  fn test_render_quote() {
+    assert_eq!(
+      render(parse("a [b]x[quote=lua <3]hi\n[quote]nested[/quote][/quote]y[/b]"), false),
+      concat!(
+        "<div>a <strong>x</strong></div>",
+        "<blockquote><cite>lua &lt;3</cite><div><strong>hi</strong></div>",
+        "<blockquote><div><strong>nested</strong></div></blockquote></blockquote>",
+        "<div><strong>y</strong></div>",
+      )
+    );
+    assert_eq!(render(parse("a\n[quote]q\n[/quote]\nb"), false), "<div>a</div><blockquote><div>q</div></blockquote><div>b</div>");
+    assert_eq!(render(parse("[quote]unclosed"), false), "<blockquote><div>unclosed</div></blockquote>");
+    assert_eq!(render(parse("x[/quote]"), false), "<div>x</div>");
+  }
+
+  #[test]
+  fn test_render_quote_with_link() {
+    assert_eq!(
+      render(parse("[url]a.com[quote]b[/quote][/url]"), false),
+      "<div><a href=\"https://a.comb\">a.com</a></div><blockquote><div><a href=\"https://a.comb\">b</a></div></blockquote>"
+    );
+  }
+
+  #[test]
+  fn test_render_quote_editor() {
+    assert_eq!(
+      render(parse("[quote=lua]hi\nthere[/quote]"), true),
+      concat!(
+        "<div><span class=\"sillycode-meta\">[quote=lua]</span>hi</div>",
+        "<div>there<span class=\"sillycode-meta\">[/quote]</span></div>",
+      )
+    );
+  }
+
+  #[test]
+  fn test_render_plain_quote() {
+    assert_eq!(
+      render_plain(parse("a [quote=lua]hi\n[quote]nested[/quote]there[/quote]\nb")),
+      "a \n> lua:\n> hi\n> > nested\n> there\nb"
+    );
+    assert_eq!(render_plain(parse("[quote][quote]a[/quote][/quote]")), "> > a");
+    assert_eq!(render_plain(parse("[quote][quote][/quote][/quote]")), "");
+    assert_eq!(render_plain(parse("[quote][quote][/quote]x[/quote]")), "> x");
+    assert_eq!(render_plain(parse("a\n[quote=b][quote][quote][/quote][/quote][/quote]")), "a\n> b:");
+    assert_eq!(render_plain(parse("a\n[quote]q\n[/quote]\nb")), render_plain(parse("a\n[quote]q[/quote]b")));
+  }
+
+  #[test]
+  fn test_render_plain() {
+    assert_eq!(render_plain(parse("[b]bold[/b] \\[b] [color=#ff0000]red\nline[/color] [:3]")), "bold [b] red\nline [:3]");
+  }
+
+  /// backend that logs every call, for testing the engine
+  #[derive(Default)]
+  struct LogBackend(Vec<String>);
+
+  impl RenderBackend for LogBackend {
+    fn open(&mut self, element: &Element) { self.0.push(format!("open {element:?}")); }
+    fn close(&mut self, element: &Element) { self.0.push(format!("close {element:?}")); }
+    fn text(&mut self, text: &str) { self.0.push(format!("text {text}")); }
+    fn emote(&mut self, emote: EmoteKind) { self.0.push(format!("emote {emote:?}")); }
+    fn line_break(&mut self) { self.0.push("line break".to_string()); }
+    fn finish(&mut self, links: &[String]) { self.0.push(format!("finish {links:?}")); }
+  }
+
+  #[test]
+  fn test_render_engine_resolves_overlap() {
+    let backend = RenderEngine::new(LogBackend::default())
+      .render(parse("[b]a[i]b[/b]c\n[url]d[:)][/url]"));
+    assert_eq!(backend.0, vec![
+      "open Strong", "text a", "open Em", "text b",
+      "close Em", "close Strong", "open Em", "text c",
+      "close Em", "line break", "open Em",
+      "open A { id: 0 }", "text d", "emote Smile", "close A { id: 0 }",
+      "close Em", "finish [\"d\"]",
+    ]);
+  }
+
+  #[test]
+  fn test_render_engine_step_by_step() {
+    let mut engine = RenderEngine::new(LogBackend::default());
+    engine.part(&Part::Style(StyleKind::Link, true));
+    engine.part(&Part::Color(Color::new(255, 0, 0), true));
+    engine.part(&Part::Text("example.com".to_string()));
+    assert_eq!(engine.elements(), &[Element::A { id: 0 }, Element::Span { color: Color::new(255, 0, 0) }]);
+    assert_eq!(engine.links(), &["example.com".to_string()]);
+  }
+
+  proptest! {
+
+    #[test]
+    fn prop_render_never_nests_links(input in "(\\[/?(b|url)\\]|\\[url=a\\.com\\]|[ab.\n]){0,32}") {
+      for is_editor in [false, true] {
+        let html = render(parse(&input), is_editor);
+        let mut tags: Vec<(usize, bool)> = html.match_indices("<a ").map(|(i, _)| (i, true))
+          .chain(html.match_indices("</a>").map(|(i, _)| (i, false)))
+          .collect();
+        tags.sort();
+        let mut depth = 0;
+        for (_, open) in tags {
+          depth = if open { depth + 1 } else { depth - 1 };
+          prop_assert!(depth <= 1, "nested link in {}", html);
+        }
+      }
+    }
+
+  }
+
 }
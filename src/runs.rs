@@ -0,0 +1,230 @@
+use crate::parser::*;
+use crate::link::{encode_href, escape_href};
+use crate::transform::{transition, Open, OpenParts};
+
+/// Content of a [Run].
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunContent {
+  /// Plain text content.
+  Text(String),
+  /// Emoticon image.
+  Emote(EmoteKind),
+  /// Line break.
+  Newline,
+  /// Inline code, shown verbatim.
+  Code(String),
+  /// Code block, shown verbatim outside of any formatting or link, but
+  /// still inside its quotes.
+  CodeBlock(String),
+}
+
+/// A piece of content together with all formatting that applies to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+  /// Text, emote or line break.
+  pub content: RunContent,
+  /// Whether the content is bold.
+  pub bold: bool,
+  /// Whether the content is italic.
+  pub italic: bool,
+  /// Whether the content is underlined.
+  pub underline: bool,
+  /// Whether the content is struck through.
+  pub strikethrough: bool,
+  /// Whether the content is hidden in a spoiler.
+  pub spoiler: bool,
+  /// Whether the content is superscript.
+  pub superscript: bool,
+  /// Whether the content is subscript.
+  pub subscript: bool,
+  /// Whether the content is small.
+  pub small: bool,
+  /// Stack of colors applied to the content, the last one is visible.
+  pub colors: Vec<Color>,
+  /// Resolved href of the innermost link containing the content, `None` if
+  /// the content is not in a link or the link is not a valid http(s) URL.
+  pub link: Option<String>,
+  /// Stack of quotes containing the content, with their authors, from the
+  /// outermost to the innermost.
+  pub quotes: Vec<Option<String>>,
+}
+
+impl Run {
+
+  /// Creates a new run without any formatting.
+  pub fn new(content: RunContent) -> Self {
+    Self {
+      content,
+      bold: false,
+      italic: false,
+      underline: false,
+      strikethrough: false,
+      spoiler: false,
+      superscript: false,
+      subscript: false,
+      small: false,
+      colors: Vec::new(),
+      link: None,
+      quotes: Vec::new(),
+    }
+  }
+
+  /// checks if two runs have the same formatting
+  fn same_format(&self, other: &Run) -> bool {
+    self.bold == other.bold
+      && self.italic == other.italic
+      && self.underline == other.underline
+      && self.strikethrough == other.strikethrough
+      && self.spoiler == other.spoiler
+      && self.superscript == other.superscript
+      && self.subscript == other.subscript
+      && self.small == other.small
+      && self.colors == other.colors
+      && self.link == other.link
+      && self.quotes == other.quotes
+  }
+
+}
+
+/// Converts parts into a flat list of formatted runs.
+///
+/// Formatting is resolved the same way as the renderer's element stack, and
+/// link hrefs are resolved like the rendered `href`. Adjacent text with the
+/// same formatting is merged into a single run.
+pub fn to_runs(parts: &[Part]) -> Vec<Run> {
+  let mut open = OpenParts::default();
+  // runs with the id of their innermost link
+  let mut runs: Vec<(Run, Option<usize>)> = Vec::new();
+  // text of every link, indexed by link id
+  let mut links: Vec<String> = Vec::new();
+
+  for part in parts {
+    let content = match part {
+      Part::Text(text) => RunContent::Text(text.clone()),
+      Part::Emote(emote) => RunContent::Emote(*emote),
+      Part::Newline => RunContent::Newline,
+      Part::Code(code) => RunContent::Code(code.clone()),
+      Part::CodeBlock(code) => RunContent::CodeBlock(code.clone()),
+      _ => {
+        open.apply(part);
+        continue;
+      }
+    };
+
+    let mut run = Run::new(content);
+    let mut link = None;
+    let block = matches!(run.content, RunContent::CodeBlock(_));
+
+    for entry in open.stack.iter() {
+      match entry {
+        Open::Quote(author) => run.quotes.push(author.clone()),
+        // code blocks close everything but quotes around them, like the renderer does
+        _ if block => (),
+        Open::Style(StyleKind::Bold) => run.bold = true,
+        Open::Style(StyleKind::Italic) => run.italic = true,
+        Open::Style(StyleKind::Underline) => run.underline = true,
+        Open::Style(StyleKind::Strikethrough) => run.strikethrough = true,
+        Open::Style(StyleKind::Spoiler) => run.spoiler = true,
+        Open::Style(StyleKind::Superscript) => run.superscript = true,
+        Open::Style(StyleKind::Subscript) => run.subscript = true,
+        Open::Style(StyleKind::Small) => run.small = true,
+        Open::Style(StyleKind::Link) => (),
+        Open::Color(color) => run.colors.push(*color),
+        Open::Link(id, href) => {
+          if links.len() <= *id {
+            links.resize(*id + 1, String::new());
+          }
+          // every open link without an href collects the text, like the renderer does
+          match (href, &run.content) {
+            (Some(href), _) => links[*id].clone_from(href),
+            (None, RunContent::Text(text) | RunContent::Code(text)) => links[*id].push_str(text),
+            (None, _) => (),
+          }
+          link = Some(*id);
+        }
+      }
+    }
+
+    // merge with the previous run if possible
+    if let (RunContent::Text(text), Some((last, last_link))) = (&run.content, runs.last_mut()) {
+      if *last_link == link && last.same_format(&run) {
+        if let RunContent::Text(last_text) = &mut last.content {
+          last_text.push_str(text);
+          continue;
+        }
+      }
+    }
+
+    runs.push((run, link));
+  }
+
+  runs.into_iter()
+    .map(|(mut run, link)| {
+      run.link = link.and_then(|id| escape_href(&links[id]));
+      run
+    })
+    .collect()
+}
+
+/// Converts formatted runs back into parts.
+///
+/// Consecutive runs with the same link share one `[url=...]` tag, which
+/// keeps the href of the runs regardless of their text, and consecutive runs
+/// in the same quotes share them.
+pub fn from_runs(runs: &[Run]) -> Vec<Part> {
+  let mut output = Vec::new();
+  let mut emitted = Vec::new();
+  let mut link_id = 0;
+  let mut previous_link: Option<&String> = None;
+
+  for run in runs {
+    // build the stack this run needs, quotes and then links go first so that
+    // they can span runs with different formatting
+    let mut target: Vec<Open> = run.quotes.iter().cloned().map(Open::Quote).collect();
+    // links that can't be written as a tag are left out
+    if let Some(link) = &run.link {
+      if previous_link != Some(link) {
+        link_id += 1;
+      }
+      if let Some(Part::Link(href)) = Part::parse_link_tag(&format!("url={}", encode_href(link))) {
+        target.push(Open::Link(link_id, Some(href)));
+      }
+    }
+    previous_link = run.link.as_ref();
+    target.extend(run.colors.iter().map(|color| Open::Color(*color)));
+    for (enabled, style) in [
+      (run.bold, StyleKind::Bold),
+      (run.italic, StyleKind::Italic),
+      (run.underline, StyleKind::Underline),
+      (run.strikethrough, StyleKind::Strikethrough),
+      (run.spoiler, StyleKind::Spoiler),
+      (run.superscript, StyleKind::Superscript),
+      (run.subscript, StyleKind::Subscript),
+      (run.small, StyleKind::Small),
+    ] {
+      if enabled {
+        target.push(Open::Style(style));
+      }
+    }
+
+    transition(&mut output, &mut emitted, &target);
+
+    match &run.content {
+      RunContent::Text(text) => push_text(&mut output, text),
+      RunContent::Emote(emote) => output.push(Part::Emote(*emote)),
+      RunContent::Newline => output.push(Part::Newline),
+      // code that can't be written as a tag is written as text instead
+      RunContent::Code(code) => match parse(&Part::Code(code.clone()).to_string()).as_slice() {
+        [part @ Part::Code(_)] => output.push(part.clone()),
+        _ => push_text(&mut output, code),
+      },
+      RunContent::CodeBlock(code) => match parse(&Part::CodeBlock(code.clone()).to_string()).as_slice() {
+        [part @ Part::CodeBlock(_)] => output.push(part.clone()),
+        _ => push_text(&mut output, code),
+      },
+    }
+  }
+
+  transition(&mut output, &mut emitted, &[]);
+  output
+}
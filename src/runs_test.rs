@@ -0,0 +1,105 @@
+#[cfg(test)]
+mod tests {
+
+  use crate::parser::*;
+  use crate::runs::*;
+
+  /// creates a text run with the given formatting applied
+  fn text(text: &str, apply: impl FnOnce(&mut Run)) -> Run {
+    let mut run = Run::new(RunContent::Text(text.to_string()));
+    apply(&mut run);
+    run
+  }
+
+  #[test]
+  fn test_to_runs_nothing() {
+    assert_eq!(to_runs(&[]), vec![]);
+  }
+
+  #[test]
+  fn test_to_runs_styles() {
+    assert_eq!(
+      to_runs(&parse("this [b]text has [i]weird[/b] nest[/i]ing")),
+      vec![
+        text("this ", |_| ()),
+        text("text has ", |r| r.bold = true),
+        text("weird", |r| { r.bold = true; r.italic = true; }),
+        text(" nest", |r| r.italic = true),
+        text("ing", |_| ()),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_to_runs_colors_emotes_and_newlines() {
+    let red = Color::new(255, 0, 0);
+    let green = Color::new(0, 255, 0);
+    assert_eq!(
+      to_runs(&parse("[color=#ff0000]red [color=#00ff00][:3][/color]\n\\[b][/color]")),
+      vec![
+        text("red ", |r| r.colors = vec![red]),
+        Run { colors: vec![red, green], ..Run::new(RunContent::Emote(EmoteKind::ColonThree)) },
+        Run { colors: vec![red], ..Run::new(RunContent::Newline) },
+        text("[b]", |r| r.colors = vec![red]),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_to_runs_links() {
+    assert_eq!(
+      to_runs(&parse("[url]example.com/[b]path[/b][/url] and [url]this is a link: [url]https://example.com[/url][/url]")),
+      vec![
+        text("example.com/", |r| r.link = Some("https://example.com/path".to_string())),
+        text("path", |r| { r.bold = true; r.link = Some("https://example.com/path".to_string()); }),
+        text(" and ", |_| ()),
+        text("this is a link: ", |_| ()),
+        text("https://example.com", |r| r.link = Some("https://example.com".to_string())),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_from_runs() {
+    let runs = vec![
+      text("plain [b] ", |_| ()),
+      text("bold", |r| r.bold = true),
+      text(" and italic", |r| { r.bold = true; r.italic = true; }),
+      Run { colors: vec![Color::new(255, 0, 0)], ..Run::new(RunContent::Emote(EmoteKind::Smile)) },
+      text("click ", |r| r.link = Some("https://example.com".to_string())),
+      text("here", |r| { r.bold = true; r.link = Some("https://example.com".to_string()); }),
+    ];
+    assert_eq!(
+      to_markup(&from_runs(&runs)),
+      "plain [b\\] [b]bold[i] and italic[/i][/b][color=#ff0000][:)][/color][url=https://example.com]click [b]here[/b][/url]"
+    );
+  }
+
+  #[test]
+  fn test_from_runs_encodes_href() {
+    let runs = to_runs(&parse("[url]x.com/a\\]b[/url]"));
+    let markup = to_markup(&from_runs(&runs));
+    assert_eq!(markup, "[url=https://x.com/a%5Db]x.com/a]b[/url]");
+    assert_eq!(parse(&markup)[0], Part::Link("https://x.com/a%5Db".to_string()));
+  }
+
+  #[test]
+  fn test_runs_round_trip() {
+    let inputs = [
+      "this [b]text has [i]weird[/b] nest[/i]ing",
+      "[color=#ff0000]red [color=#00ff00]green[/color] red[/color]\n[u][s]both[/u][/s]",
+      "[url]https://a.com[/url][url]https://b.com[/url]",
+      "[url=a.com]click [i]here[/i][/url] or [url=b.com]there[/url]",
+      "[b][code]x [:3][/code][/b] [url][code]example.com[/code][/url]",
+      "[i]a[codeblock]b\n[/i]c[/codeblock]d[/i] [url]x.com[codeblock]y[/codeblock][/url]",
+      "[quote=lua][b]a[quote]b[codeblock]c[/codeblock][/b][/quote][/quote]",
+      "[spoiler]a [b]b[/spoiler] c[/b]",
+      "x[sup]2 [small]y[/sup]z[/small] [sub]i[/sub]",
+    ];
+    for input in inputs {
+      let runs = to_runs(&parse(input));
+      assert_eq!(to_runs(&from_runs(&runs)), runs);
+    }
+  }
+
+}
@@ -0,0 +1,137 @@
+//! Serde support for the types in [crate::parser].
+//!
+//! Parts serialize to the same JSON shape as the TypeScript `Part` type in
+//! `lib/parser.ts`, so both implementations can exchange parsed markup.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use strum::IntoEnumIterator;
+
+use crate::parser::*;
+
+impl Serialize for StyleKind {
+
+  /// Serializes the style as its tag name like `"b"`.
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.to_tag())
+  }
+
+}
+
+impl<'de> Deserialize<'de> for StyleKind {
+
+  /// Deserializes the style from its tag name like `"b"`.
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let tag = String::deserialize(deserializer)?;
+    StyleKind::iter()
+      .find(|style| style.to_tag() == tag)
+      .ok_or_else(|| de::Error::custom(format!("unknown style {tag:?}")))
+  }
+
+}
+
+impl Serialize for EmoteKind {
+
+  /// Serializes the emote as its name like `"smile"`.
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.to_name())
+  }
+
+}
+
+impl<'de> Deserialize<'de> for EmoteKind {
+
+  /// Deserializes the emote from its name like `"smile"`.
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    EmoteKind::iter()
+      .find(|emote| emote.to_name() == name)
+      .ok_or_else(|| de::Error::custom(format!("unknown emote {name:?}")))
+  }
+
+}
+
+impl Serialize for Color {
+
+  /// Serializes the color as a hexadecimal string like `"#ad77f1"`.
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+
+}
+
+impl<'de> Deserialize<'de> for Color {
+
+  /// Deserializes the color from a hexadecimal string like `"#ad77f1"`.
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let string = String::deserialize(deserializer)?;
+    string.parse().map_err(de::Error::custom)
+  }
+
+}
+
+/// serialized representation of a [Part], matching the TypeScript `Part` type
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum PartRepr {
+  Text { text: String },
+  Escape,
+  Newline,
+  Style { style: StyleKind, enable: bool },
+  Color {
+    enable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    color: Option<Color>,
+  },
+  Emote { emote: EmoteKind },
+  Link { href: String },
+  Code { code: String },
+  CodeBlock { code: String },
+  Quote {
+    enable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author: Option<String>,
+  },
+}
+
+impl Serialize for Part {
+
+  /// Serializes the part as an object like `{ "type": "text", "text": "hi" }`.
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let repr = match self.clone() {
+      Part::Text(text) => PartRepr::Text { text },
+      Part::Escape => PartRepr::Escape,
+      Part::Newline => PartRepr::Newline,
+      Part::Style(style, enable) => PartRepr::Style { style, enable },
+      Part::Color(color, enable) => PartRepr::Color { enable, color: enable.then_some(color) },
+      Part::Emote(emote) => PartRepr::Emote { emote },
+      Part::Link(href) => PartRepr::Link { href },
+      Part::Code(code) => PartRepr::Code { code },
+      Part::CodeBlock(code) => PartRepr::CodeBlock { code },
+      Part::Quote(author, enable) => PartRepr::Quote { enable, author: author.filter(|_| enable) },
+    };
+    repr.serialize(serializer)
+  }
+
+}
+
+impl<'de> Deserialize<'de> for Part {
+
+  /// Deserializes the part from an object like `{ "type": "text", "text": "hi" }`.
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    Ok(match PartRepr::deserialize(deserializer)? {
+      PartRepr::Text { text } => Part::Text(text),
+      PartRepr::Escape => Part::Escape,
+      PartRepr::Newline => Part::Newline,
+      PartRepr::Style { style, enable } => Part::Style(style, enable),
+      PartRepr::Color { enable: true, color: Some(color) } => Part::Color(color, true),
+      PartRepr::Color { enable: true, color: None } => return Err(de::Error::missing_field("color")),
+      PartRepr::Color { enable: false, .. } => Part::Color(Color::default(), false),
+      PartRepr::Emote { emote } => Part::Emote(emote),
+      PartRepr::Link { href } => Part::Link(href),
+      PartRepr::Code { code } => Part::Code(code),
+      PartRepr::CodeBlock { code } => Part::CodeBlock(code),
+      PartRepr::Quote { enable, author } => Part::Quote(author.filter(|_| enable), enable),
+    })
+  }
+
+}
@@ -0,0 +1,51 @@
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+
+  use crate::parser::*;
+
+  #[test]
+  fn test_serialize_parts() {
+    let parts = parse("\\[[b]hi[/b]\n[color=#A834cf][:3][/color]");
+    assert_eq!(
+      serde_json::to_string(&parts).unwrap(),
+      concat!(
+        "[{\"type\":\"escape\"},",
+        "{\"type\":\"text\",\"text\":\"[\"},",
+        "{\"type\":\"style\",\"style\":\"b\",\"enable\":true},",
+        "{\"type\":\"text\",\"text\":\"hi\"},",
+        "{\"type\":\"style\",\"style\":\"b\",\"enable\":false},",
+        "{\"type\":\"newline\"},",
+        "{\"type\":\"color\",\"enable\":true,\"color\":\"#A834cf\"},",
+        "{\"type\":\"emote\",\"emote\":\"colonthree\"},",
+        "{\"type\":\"color\",\"enable\":false}]"
+      )
+    );
+    assert_eq!(
+      serde_json::to_string(&parse("[url=example.com]x[/url]")).unwrap(),
+      concat!(
+        "[{\"type\":\"link\",\"href\":\"example.com\"},",
+        "{\"type\":\"text\",\"text\":\"x\"},",
+        "{\"type\":\"style\",\"style\":\"url\",\"enable\":false}]"
+      )
+    );
+  }
+
+  #[test]
+  fn test_deserialize_parts() {
+    let input = "[url]https://example.com[/url] [url=example.com]x[/url] [code][b][/code] [codeblock]a\nb[/codeblock] [quote=lua][quote]q[/quote][/quote] [color=#ff0000]red [:D][/color] \\\\";
+    let parts = parse(input);
+    let json = serde_json::to_string(&parts).unwrap();
+    let deserialized: Vec<Part> = serde_json::from_str(&json).unwrap();
+    assert_eq!(deserialized, parts);
+    assert_eq!(to_markup(&deserialized), input);
+  }
+
+  #[test]
+  fn test_deserialize_invalid_parts() {
+    assert!(serde_json::from_str::<Part>("{\"type\":\"style\",\"style\":\"blink\",\"enable\":true}").is_err());
+    assert!(serde_json::from_str::<Part>("{\"type\":\"emote\",\"emote\":\":)\"}").is_err());
+    assert!(serde_json::from_str::<Part>("{\"type\":\"color\",\"enable\":true}").is_err());
+    assert!(serde_json::from_str::<Part>("{\"type\":\"color\",\"enable\":true,\"color\":\"red\"}").is_err());
+  }
+
+}
@@ -0,0 +1,666 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::link::{encode_href, escape_href};
+use crate::parser::*;
+
+/// an open style, color, link or quote
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Open {
+  Style(StyleKind),
+  Color(Color),
+  /// quotes have an author if they were opened with one
+  Quote(Option<String>),
+  /// links are told apart by the order they were opened in,
+  /// and have an href if they were opened with [Part::Link]
+  Link(usize, Option<String>),
+}
+
+impl Open {
+
+  /// returns the part that opens this
+  pub(crate) fn opening(&self) -> Part {
+    match self {
+      Open::Style(style) => Part::Style(*style, true),
+      Open::Color(color) => Part::Color(*color, true),
+      Open::Quote(author) => Part::Quote(author.clone(), true),
+      Open::Link(_, None) => Part::Style(StyleKind::Link, true),
+      Open::Link(_, Some(href)) => Part::Link(href.clone()),
+    }
+  }
+
+  /// returns the part that closes this
+  pub(crate) fn closing(&self) -> Part {
+    match self {
+      Open::Style(style) => Part::Style(*style, false),
+      Open::Color(_) => Part::Color(Color::default(), false),
+      Open::Quote(_) => Part::Quote(None, false),
+      Open::Link(_, _) => Part::Style(StyleKind::Link, false),
+    }
+  }
+
+}
+
+/// tracks the open styles, colors, links and quotes the same way the renderer's
+/// element stack does
+#[derive(Default, Debug, Clone)]
+pub(crate) struct OpenParts {
+  /// open stack, in the order they were opened
+  pub(crate) stack: Vec<Open>,
+  /// counter for link ids
+  links: usize,
+}
+
+impl OpenParts {
+
+  /// removes the most recently opened entry matching the predicate
+  fn remove(&mut self, predicate: impl Fn(&Open) -> bool) {
+    if let Some(index) = self.stack.iter().rposition(predicate) {
+      self.stack.remove(index);
+    }
+  }
+
+  /// updates the open stack with a new part
+  pub(crate) fn apply(&mut self, part: &Part) {
+    match part {
+      // links always push a new link, closing the open one like the renderer does
+      Part::Style(StyleKind::Link, true) => {
+        self.remove(|o| matches!(o, Open::Link(_, _)));
+        self.stack.push(Open::Link(self.links, None));
+        self.links += 1;
+      }
+      Part::Link(href) => {
+        self.remove(|o| matches!(o, Open::Link(_, _)));
+        self.stack.push(Open::Link(self.links, Some(href.clone())));
+        self.links += 1;
+      }
+      Part::Style(StyleKind::Link, false) => self.remove(|o| matches!(o, Open::Link(_, _))),
+      // other styles are only pushed if they are not already open
+      Part::Style(style, true) if !self.stack.contains(&Open::Style(*style)) => self.stack.push(Open::Style(*style)),
+      Part::Style(style, false) => self.remove(|o| o == &Open::Style(*style)),
+      Part::Color(color, true) => self.stack.push(Open::Color(*color)),
+      Part::Color(_, false) => self.remove(|o| matches!(o, Open::Color(_))),
+      // quotes are blocks, so they stay below everything else, which the
+      // renderer closes and reopens around them anyway
+      Part::Quote(author, true) => {
+        let index = self.stack.iter().take_while(|o| matches!(o, Open::Quote(_))).count();
+        self.stack.insert(index, Open::Quote(author.clone()));
+      }
+      Part::Quote(_, false) => self.remove(|o| matches!(o, Open::Quote(_))),
+      _ => (),
+    }
+  }
+
+  /// returns the parts that reopen the open stack, in order
+  fn opening(&self) -> impl Iterator<Item = Part> + '_ {
+    self.stack.iter().map(Open::opening)
+  }
+
+  /// returns the parts that close the open stack, in reverse order
+  fn closing(&self) -> impl Iterator<Item = Part> + '_ {
+    self.stack.iter().rev().map(Open::closing)
+  }
+
+}
+
+/// collects the text of every link that takes its href from its text, by the
+/// ids [OpenParts] gives them, starting from the `open` state
+fn link_texts<'a>(parts: impl IntoIterator<Item = &'a Part>, open: &OpenParts) -> Vec<String> {
+  let mut open = open.clone();
+  let mut texts = vec![String::new(); open.links];
+
+  for part in parts {
+    open.apply(part);
+    texts.resize(open.links, String::new());
+    if let Part::Text(text) | Part::Code(text) = part {
+      for entry in open.stack.iter() {
+        if let Open::Link(id, None) = entry {
+          texts[*id].push_str(text);
+        }
+      }
+    }
+  }
+
+  texts
+}
+
+/// returns the part that opens a link with the href that its whole text
+/// resolves to, or `None` if the text is not a valid link or the href is too
+/// long to be written as a tag
+fn whole_link(text: &str) -> Option<Part> {
+  let href = escape_href(text)?;
+  Part::parse_link_tag(&format!("url={}", encode_href(&href)))
+}
+
+/// gives every link in `output` that takes its href from its text, but only
+/// has part of its text from `texts`, a `[url=...]` tag with the href of its
+/// whole text instead, or drops it if its whole text is not a valid link,
+/// `output` has to start by reopening the `open` state
+fn resolve_cut_links(output: &mut Vec<Part>, open: &OpenParts, texts: &[String]) {
+  let collected = link_texts(output.get(open.stack.len()..).unwrap_or_default(), open);
+
+  // find the parts that open links, and whether they close another link
+  let mut links = Vec::new();
+  let mut tracker = open.clone();
+  for (index, part) in output.iter().enumerate() {
+    match open.stack.get(index) {
+      Some(Open::Link(id, None)) => links.push((index, *id, false)),
+      Some(_) => (),
+      None if *part == Part::Style(StyleKind::Link, true) => {
+        let closes = tracker.stack.iter().any(|o| matches!(o, Open::Link(_, _)));
+        links.push((index, tracker.links, closes));
+        tracker.apply(part);
+      }
+      None => tracker.apply(part),
+    }
+  }
+
+  for (index, id, closes) in links.into_iter().rev() {
+    if collected[id] == texts[id] {
+      continue;
+    }
+    match whole_link(&texts[id]) {
+      Some(part) => output[index] = part,
+      None if closes => output[index] = Part::Style(StyleKind::Link, false),
+      None => { output.remove(index); }
+    }
+  }
+}
+
+/// Options for [truncate_with].
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct TruncateOptions {
+  /// How the length is measured, the same as for [length_with].
+  pub length: LengthOptions,
+  /// Whether to prefer cutting at whitespace instead of in the middle of a word.
+  pub words: bool,
+}
+
+/// cuts text to fit in the budget, never splitting a grapheme
+fn cut_text<'a>(text: &'a str, budget: usize, options: &TruncateOptions) -> &'a str {
+  let mut end = 0;
+  let mut used = 0;
+
+  for (index, grapheme) in text.grapheme_indices(true) {
+    used += options.length.metric.measure(grapheme);
+    if used > budget {
+      break;
+    }
+    end = index + grapheme.len();
+  }
+
+  let mut cut = &text[..end];
+
+  if options.words {
+    // back off to the last whitespace if we are in the middle of a word
+    if !text[end..].starts_with(char::is_whitespace) {
+      if let Some(index) = cut.rfind(char::is_whitespace) {
+        cut = &cut[..index];
+      }
+    }
+    cut = cut.trim_end();
+  }
+
+  cut
+}
+
+/// Truncates parts to a maximum display length, appending an ellipsis.
+///
+/// See [truncate_with] for details, this measures the length like [length].
+pub fn truncate(parts: &[Part], max_len: usize, ellipsis: &str) -> Vec<Part> {
+  truncate_with(parts, max_len, ellipsis, &TruncateOptions::default())
+}
+
+/// Truncates parts to a maximum display length, appending an ellipsis.
+///
+/// If the parts already fit they are returned unchanged. Otherwise they are
+/// cut so that together with the ellipsis they fit in `max_len`, never in the
+/// middle of a tag or a grapheme. Styles and colors that would be left empty
+/// are dropped, and everything still open is closed, so the result renders
+/// exactly like the start of the original. A `[url]` link that is cut keeps
+/// pointing to the href of its whole text.
+pub fn truncate_with(parts: &[Part], max_len: usize, ellipsis: &str, options: &TruncateOptions) -> Vec<Part> {
+  if length_with(parts, &options.length) <= max_len {
+    return parts.to_vec();
+  }
+
+  let mut budget = max_len.saturating_sub(options.length.metric.measure(ellipsis));
+  let mut output = Vec::new();
+
+  for part in parts {
+    let size = options.length.measure(part);
+    if size <= budget {
+      budget -= size;
+      output.push(part.clone());
+      continue;
+    }
+
+    match part {
+      Part::Text(text) => {
+        let cut = cut_text(text, budget, options);
+        if !cut.is_empty() {
+          output.push(Part::Text(cut.to_string()));
+        }
+      }
+      Part::Code(code) => {
+        let cut = cut_text(code, budget, options);
+        if !cut.is_empty() {
+          output.push(Part::Code(cut.to_string()));
+        }
+      }
+      Part::CodeBlock(code) => {
+        let cut = cut_text(code, budget, options);
+        if !cut.is_empty() {
+          output.push(Part::CodeBlock(cut.to_string()));
+        }
+      }
+      _ => (),
+    }
+
+    break;
+  }
+
+  // drop anything that would be left empty at the end
+  while matches!(output.last(), Some(Part::Escape | Part::Style(_, true) | Part::Color(_, true) | Part::Link(_) | Part::Quote(_, true))) {
+    output.pop();
+  }
+
+  resolve_cut_links(&mut output, &OpenParts::default(), &link_texts(parts, &OpenParts::default()));
+
+  let mut open = OpenParts::default();
+  for part in output.iter() {
+    open.apply(part);
+  }
+
+  push_text(&mut output, ellipsis);
+  output.extend(open.closing());
+  output
+}
+
+/// an indivisible piece of parts, used when splitting
+#[derive(Debug, Clone, Copy)]
+enum Atom<'a> {
+  /// a whole part that is not text
+  Part(&'a Part),
+  /// a single grapheme of text or code, with the index of the part it came
+  /// from and that part
+  Grapheme(usize, &'a Part, &'a str),
+}
+
+impl Atom<'_> {
+
+  /// calculates the length of the atom
+  fn size(&self, options: &LengthOptions) -> usize {
+    match self {
+      Atom::Part(part) => options.measure(part),
+      Atom::Grapheme(_, _, grapheme) => options.metric.measure(grapheme),
+    }
+  }
+
+  /// checks if the atom is a newline, or a line break in a code block
+  fn is_newline(&self) -> bool {
+    matches!(self, Atom::Part(Part::Newline) | Atom::Grapheme(_, Part::CodeBlock(_), "\n" | "\r\n"))
+  }
+
+  /// checks if the atom is whitespace in text or inline code, code blocks
+  /// are only split at line breaks so that their indentation is kept
+  fn is_whitespace(&self) -> bool {
+    matches!(self, Atom::Grapheme(_, Part::Text(_) | Part::Code(_), grapheme) if grapheme.chars().all(char::is_whitespace))
+  }
+
+}
+
+/// builds a chunk from atoms, reopening the open parts at the start and
+/// closing everything that is still open at the end, links are checked
+/// against their whole text from `texts`
+fn assemble(atoms: &[Atom], open: &OpenParts, texts: &[String]) -> Vec<Part> {
+  let mut output: Vec<Part> = open.opening().collect();
+  let mut source = None;
+
+  for atom in atoms {
+    match atom {
+      Atom::Grapheme(index, part, grapheme) => {
+        // join graphemes from the same part back together
+        if source == Some(*index) {
+          if let Some(Part::Text(text) | Part::Code(text) | Part::CodeBlock(text)) = output.last_mut() {
+            text.push_str(grapheme);
+            continue;
+          }
+        }
+        source = Some(*index);
+        output.push(match part {
+          Part::Code(_) => Part::Code(grapheme.to_string()),
+          Part::CodeBlock(_) => Part::CodeBlock(grapheme.to_string()),
+          _ => Part::Text(grapheme.to_string()),
+        });
+      }
+      Atom::Part(part) => {
+        source = None;
+        output.push((*part).clone());
+      }
+    }
+  }
+
+  // drop anything that would be left empty at the end
+  while matches!(output.last(), Some(Part::Escape | Part::Style(_, true) | Part::Color(_, true) | Part::Link(_) | Part::Quote(_, true))) {
+    output.pop();
+  }
+
+  resolve_cut_links(&mut output, open, texts);
+
+  let mut open = OpenParts::default();
+  for part in output.iter() {
+    open.apply(part);
+  }
+
+  output.extend(open.closing());
+  output
+}
+
+/// Splits parts into chunks that each fit in a maximum display length.
+///
+/// See [split_with] for details, this measures the length like [length].
+pub fn split(parts: &[Part], max_len: usize) -> Vec<Vec<Part>> {
+  split_with(parts, max_len, &LengthOptions::default())
+}
+
+/// Splits parts into chunks that each fit in a maximum display length.
+///
+/// Chunks are split at a newline if possible, then at whitespace, and
+/// otherwise between graphemes, and the newline or whitespace character at
+/// the split is dropped. Code is split the same way and each piece is kept
+/// as code, but code blocks are only split at their line breaks or between
+/// graphemes. Like the renderer does at every line break, all open
+/// styles, colors and links are closed at the end of each chunk and reopened
+/// at the start of the next one, so each chunk can be rendered on its own.
+/// A `[url]` link that is split keeps pointing to the href of its whole text
+/// in every chunk.
+///
+/// The limit only counts what is displayed, the tags of a chunk are not part
+/// of it, so the markup of a chunk can be much longer than `max_len`. Use
+/// [split_markup] to limit the length of the markup instead.
+///
+/// A single emote or grapheme that is longer than `max_len` still gets a
+/// chunk of its own.
+pub fn split_with(parts: &[Part], max_len: usize, options: &LengthOptions) -> Vec<Vec<Part>> {
+  split_atoms(parts, max_len, Limit::Display(options))
+}
+
+/// Splits parts into chunks whose markup each fits in a maximum length.
+///
+/// See [split_markup_with] for details, this measures the markup in
+/// characters like [LengthMetric::Scalars].
+pub fn split_markup(parts: &[Part], max_len: usize) -> Vec<Vec<Part>> {
+  split_markup_with(parts, max_len, LengthMetric::default())
+}
+
+/// Splits parts into chunks whose markup each fits in a maximum length.
+///
+/// This works like [split_with], but limits the length of the markup of
+/// every chunk as written by [to_markup], measured with `metric`, including
+/// the tags that are reopened and closed in each chunk. This is the limit to
+/// use when the sillycode text itself has to fit somewhere.
+///
+/// A single tag, emote or grapheme whose markup is longer than `max_len`,
+/// together with the tags reopened around it, still gets a chunk of its own.
+pub fn split_markup_with(parts: &[Part], max_len: usize, metric: LengthMetric) -> Vec<Vec<Part>> {
+  split_atoms(parts, max_len, Limit::Markup(metric))
+}
+
+/// what the maximum length of a chunk limits
+#[derive(Debug, Clone, Copy)]
+enum Limit<'a> {
+  /// the display length, measured like [length_with]
+  Display(&'a LengthOptions),
+  /// the length of the markup of the chunk
+  Markup(LengthMetric),
+}
+
+impl Limit<'_> {
+
+  /// measures a chunk
+  fn length(&self, chunk: &[Part]) -> usize {
+    match self {
+      Limit::Display(options) => length_with(chunk, options),
+      Limit::Markup(metric) => metric.measure(&to_markup(chunk)),
+    }
+  }
+
+  /// returns how many of the atoms fit in a chunk that starts with the open
+  /// parts, but always at least one
+  fn fitting(&self, atoms: &[Atom], open: &OpenParts, texts: &[String], max_len: usize) -> usize {
+    match self {
+      Limit::Display(options) => {
+        let mut end = 0;
+        let mut used = 0;
+        while end < atoms.len() {
+          let size = atoms[end].size(options);
+          if used + size > max_len && used > 0 {
+            break;
+          }
+          used += size;
+          end += 1;
+        }
+        end
+      }
+      Limit::Markup(_) => {
+        // almost every atom is at least one character of markup, so more than
+        // max_len of them never fit, and the longest chunk that fits is searched
+        let atoms = &atoms[..atoms.len().min(max_len + 1)];
+        let (mut low, mut high) = (1, atoms.len());
+        while low < high {
+          let middle = (low + high).div_ceil(2);
+          if self.length(&assemble(&atoms[..middle], open, texts)) <= max_len {
+            low = middle;
+          } else {
+            high = middle - 1;
+          }
+        }
+        low
+      }
+    }
+  }
+
+}
+
+/// splits parts into chunks that each fit in the limit, see [split_with]
+fn split_atoms(parts: &[Part], max_len: usize, limit: Limit) -> Vec<Vec<Part>> {
+  // break the parts into atoms
+  let mut atoms = Vec::new();
+  for (index, part) in parts.iter().enumerate() {
+    match part {
+      Part::Text(text) | Part::Code(text) | Part::CodeBlock(text) => {
+        atoms.extend(text.graphemes(true).map(|grapheme| Atom::Grapheme(index, part, grapheme)));
+      }
+      _ => atoms.push(Atom::Part(part)),
+    }
+  }
+
+  let texts = link_texts(parts, &OpenParts::default());
+  let mut chunks = Vec::new();
+  let mut open = OpenParts::default();
+  let mut start = 0;
+
+  while start < atoms.len() {
+    // closing tags at the start of a chunk would only close reopened tags
+    while let Some(Atom::Part(part @ (Part::Style(_, false) | Part::Color(_, false) | Part::Quote(_, false)))) = atoms.get(start) {
+      open.apply(part);
+      start += 1;
+    }
+    if start == atoms.len() {
+      break;
+    }
+
+    // take as many atoms as fit, but always at least one
+    let end = start + limit.fitting(&atoms[start..], &open, &texts, max_len);
+
+    // keep escapes together with the character they escape
+    let mut hard = end;
+    while hard > start + 1 && matches!(atoms[hard - 1], Atom::Part(Part::Escape)) {
+      hard -= 1;
+    }
+
+    // find where to split, preferring newlines and then whitespace
+    let (cut, resume) = if end == atoms.len() {
+      (end, end)
+    } else {
+      let newline = (start + 1..=end).rev().find(|&i| atoms[i].is_newline());
+      let whitespace = (start + 1..=end).rev().find(|&i| atoms[i].is_whitespace());
+      match newline.or(whitespace) {
+        Some(i) => (i, i + 1),
+        None => (hard, hard),
+      }
+    };
+
+    // a shorter chunk can have longer markup, when it cuts a link that then
+    // needs its href written out, so that falls back to the hard split
+    let mut chunk = assemble(&atoms[start..cut], &open, &texts);
+    let resume = if cut < hard && limit.length(&chunk) > max_len {
+      chunk = assemble(&atoms[start..hard], &open, &texts);
+      hard
+    } else {
+      resume
+    };
+
+    if chunk.iter().any(|part| matches!(part, Part::Text(_) | Part::Newline | Part::Emote(_) | Part::Code(_) | Part::CodeBlock(_))) {
+      chunks.push(chunk);
+    }
+
+    for atom in atoms[start..resume].iter() {
+      if let Atom::Part(part) = atom {
+        open.apply(part);
+      }
+    }
+
+    start = resume;
+  }
+
+  chunks
+}
+
+/// emits the parts needed to get from the emitted open stack to the target,
+/// returning how many entries of the target were already open
+pub(crate) fn transition(output: &mut Vec<Part>, emitted: &mut Vec<Open>, target: &[Open]) -> usize {
+  // keep everything the two stacks have in common
+  let common = emitted.iter()
+    .zip(target.iter())
+    .take_while(|(a, b)| a == b)
+    .count();
+
+  // close the rest of the emitted stack from the top
+  while emitted.len() > common {
+    if let Some(open) = emitted.pop() {
+      output.push(open.closing());
+    }
+  }
+
+  // then open the rest of the target stack
+  for open in target[common..].iter() {
+    output.push(open.opening());
+    emitted.push(open.clone());
+  }
+
+  common
+}
+
+/// Normalizes parts into a canonical minimal form that renders the same.
+///
+/// Styles, colors and links are only opened right before the content they
+/// apply to, so redundant, unmatched and empty toggles are dropped, and a
+/// color or style that is closed and then reopened with nothing in between
+/// stays open instead. Quotes are kept where they are, even when they are
+/// empty, since they always render a block, and so is empty code. A `[url]`
+/// link that has to be closed and reopened around other tags is written with
+/// the href of its whole text. Everything is closed at the end in reverse
+/// order.
+/// Text is merged into as few parts as possible and escaped only where it
+/// is needed, and colors are written in lowercase.
+///
+/// Two inputs that differ only in these ways normalize to the same parts.
+pub fn normalize(parts: &[Part]) -> Vec<Part> {
+  let (output, opened) = normalize_links(parts, &[]);
+
+  // a link that takes its href from its text and is closed and reopened would
+  // become several links that each collect only part of the text, so those
+  // are written with the href of their whole text instead
+  let mut opened = opened;
+  opened.sort_unstable();
+  let mut reopened: Vec<usize> = opened.windows(2).filter(|pair| pair[0] == pair[1]).map(|pair| pair[0]).collect();
+  if reopened.is_empty() {
+    return output;
+  }
+  reopened.dedup();
+
+  let texts = link_texts(parts, &OpenParts::default());
+  let replaced: Vec<(usize, Option<Part>)> = reopened.into_iter()
+    .map(|id| {
+      // a link whose whole text isn't valid gets it as an href that isn't
+      // valid either, so that it still renders as text with the same elements
+      let invalid = || Part::parse_link_tag(&format!("url={}", encode_href(&texts[id])))
+        .filter(|part| matches!(part, Part::Link(href) if escape_href(href).is_none()));
+      (id, whole_link(&texts[id]).or_else(invalid))
+    })
+    .collect();
+  normalize_links(parts, &replaced).0
+}
+
+/// normalizes parts, opening the links in `replaced`, which is sorted by id,
+/// with their part instead, or leaving them out if they have none, and
+/// returns the output along with the ids of links that take their href from
+/// their text every time they are opened
+fn normalize_links(parts: &[Part], replaced: &[(usize, Option<Part>)]) -> (Vec<Part>, Vec<usize>) {
+  let mut output = Vec::new();
+  let mut open = OpenParts::default();
+  let mut emitted = Vec::new();
+  let mut opened = Vec::new();
+
+  // transitions to the target, remembering which links are opened
+  let mut emit = |output: &mut Vec<Part>, emitted: &mut Vec<Open>, target: &[Open]| {
+    let common = transition(output, emitted, target);
+    opened.extend(target[common..].iter().filter_map(|o| match o {
+      Open::Link(id, None) => Some(*id),
+      _ => None,
+    }));
+  };
+
+  for part in parts {
+    match part {
+      Part::Text(text) if text.is_empty() => (),
+      Part::Text(text) => {
+        emit(&mut output, &mut emitted, &open.stack);
+        push_text(&mut output, text);
+      }
+      // empty code still renders an element, unlike empty text
+      Part::Newline | Part::Emote(_) | Part::Code(_) | Part::CodeBlock(_) => {
+        emit(&mut output, &mut emitted, &open.stack);
+        output.push(part.clone());
+      }
+      Part::Escape => (),
+      Part::Color(color, enable) => {
+        open.apply(&Part::Color(Color::new(color.r, color.g, color.b), *enable));
+      }
+      // quotes are blocks that render even when empty, so they are kept where they are
+      Part::Quote(_, _) => {
+        let quotes = |open: &OpenParts| open.stack.iter().take_while(|o| matches!(o, Open::Quote(_))).count();
+        let before = quotes(&open);
+        open.apply(part);
+        let after = quotes(&open);
+        if after != before {
+          emit(&mut output, &mut emitted, &open.stack[..after]);
+        }
+      }
+      Part::Style(StyleKind::Link, true) => match replaced.binary_search_by_key(&open.links, |(id, _)| *id).map(|index| &replaced[index]) {
+        Ok((_, Some(link))) => open.apply(link),
+        // the link renders as text, but it still closes the link before it
+        Ok((_, None)) => {
+          open.apply(&Part::Style(StyleKind::Link, false));
+          open.links += 1;
+        }
+        Err(_) => open.apply(part),
+      },
+      Part::Style(_, _) | Part::Link(_) => open.apply(part),
+    }
+  }
+
+  emit(&mut output, &mut emitted, &[]);
+  (output, opened)
+}
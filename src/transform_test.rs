@@ -0,0 +1,349 @@
+#[cfg(test)]
+mod tests {
+
+  use crate::parser::*;
+  use crate::renderer::*;
+  use crate::transform::*;
+  use proptest::prelude::*;
+
+  /// truncates markup and formats the result back to markup
+  fn truncate_markup(input: &str, max_len: usize, ellipsis: &str) -> String {
+    to_markup(&truncate(&parse(input), max_len, ellipsis))
+  }
+
+  #[test]
+  fn test_truncate_short_enough() {
+    assert_eq!(truncate_markup("[b]hello[/b] world", 11, "..."), "[b]hello[/b] world");
+  }
+
+  #[test]
+  fn test_truncate_text() {
+    assert_eq!(truncate_markup("hello world", 8, "..."), "hello...");
+    assert_eq!(truncate_markup("hello world", 8, ""), "hello wo");
+  }
+
+  #[test]
+  fn test_truncate_closes_open_tags() {
+    assert_eq!(truncate_markup("[b]hello [i]world[/i][/b]", 9, "…"), "[b]hello [i]wo…[/i][/b]");
+    assert_eq!(truncate_markup("[color=#ff0000][url]https://example.com[/url][/color]", 8, ""), "[color=#ff0000][url=https://example.com]https://[/url][/color]");
+  }
+
+  #[test]
+  fn test_truncate_keeps_link_href() {
+    assert_eq!(
+      truncate_markup("see [url]example.com/abcdefghij[/url]", 15, "…"),
+      "see [url=https://example.com/abcdefghij]example.co…[/url]"
+    );
+    assert_eq!(truncate_markup("[url]a.com[/url] [url]b.com/xyz[/url]", 10, ""), "[url]a.com[/url] [url=https://b.com/xyz]b.co[/url]");
+    // a link whose whole text is not valid is rendered as text, so it is dropped
+    assert_eq!(truncate_markup("[url]example.com:99999[/url]", 13, ""), "example.com:9");
+    assert_eq!(truncate_markup("[url]a.com[url]example.com:99999[/url]", 18, ""), "[url]a.com[/url]example.com:9");
+  }
+
+  #[test]
+  fn test_truncate_drops_empty_toggles() {
+    assert_eq!(truncate_markup("hello[b][i] world[/i][/b]", 6, "..."), "hel...");
+    assert_eq!(truncate_markup("hello [b]world[/b]", 6, ""), "hello ");
+  }
+
+  #[test]
+  fn test_truncate_emotes_and_newlines() {
+    assert_eq!(truncate_markup("hi [:3][:3]\nthere", 5, ""), "hi [:3][:3]");
+    assert_eq!(truncate_markup("hi [:3][:3]\nthere", 4, ""), "hi [:3]");
+  }
+
+  #[test]
+  fn test_truncate_code() {
+    assert_eq!(truncate_markup("see [code][b]bold[/b][/code]", 9, "..."), "see [code][b[/code]...");
+  }
+
+  #[test]
+  fn test_truncate_never_splits_graphemes() {
+    assert_eq!(truncate_markup("family: 👨‍👩‍👧", 10, ""), "family: ");
+    let options = TruncateOptions {
+      length: LengthOptions { metric: LengthMetric::Graphemes, ..LengthOptions::default() },
+      ..TruncateOptions::default()
+    };
+    assert_eq!(to_markup(&truncate_with(&parse("family: 👨‍👩‍👧!"), 9, "", &options)), "family: 👨‍👩‍👧");
+  }
+
+  #[test]
+  fn test_truncate_escapes() {
+    assert_eq!(truncate_markup("a \\[b] b", 5, ""), "a \\[b]");
+    assert_eq!(truncate_markup("a \\[b] b", 2, ""), "a ");
+    assert_eq!(truncate_markup("[b\\] is not bold", 3, "]"), "[b\\]");
+  }
+
+  #[test]
+  fn test_truncate_words() {
+    let options = TruncateOptions { words: true, ..TruncateOptions::default() };
+    let truncate_words = |input: &str, max_len| to_markup(&truncate_with(&parse(input), max_len, "...", &options));
+    assert_eq!(truncate_words("hello wonderful world", 16), "hello...");
+    assert_eq!(truncate_words("hello wonderful world", 18), "hello wonderful...");
+    assert_eq!(truncate_words("superlongword", 8), "super...");
+  }
+
+  #[test]
+  fn test_truncate_renders_like_prefix() {
+    let parts = parse("[b]bold [color=#ff0000]red[/b] text\nline [i]two[/i][/color]");
+    assert_eq!(
+      render(truncate(&parts, 9, ""), false),
+      "<div><strong>bold <span style=\"color: #ff0000\">red</span></strong><span style=\"color: #ff0000\"> </span></div>"
+    );
+  }
+
+  /// splits markup and formats the chunks back to markup
+  fn split_to_markup(input: &str, max_len: usize) -> Vec<String> {
+    split(&parse(input), max_len).iter().map(|chunk| to_markup(chunk)).collect()
+  }
+
+  #[test]
+  fn test_split_short_enough() {
+    assert_eq!(split_to_markup("[b]hello[/b] world", 20), vec!["[b]hello[/b] world"]);
+    assert_eq!(split_to_markup("", 20), Vec::<String>::new());
+  }
+
+  #[test]
+  fn test_split_prefers_newlines() {
+    assert_eq!(split_to_markup("one two\nthree four", 12), vec!["one two", "three four"]);
+  }
+
+  #[test]
+  fn test_split_at_whitespace() {
+    assert_eq!(split_to_markup("one two three four", 10), vec!["one two", "three four"]);
+  }
+
+  #[test]
+  fn test_split_hard() {
+    assert_eq!(split_to_markup("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+    assert_eq!(split_to_markup("👨‍👩‍👧👨‍👩‍👧", 6), vec!["👨‍👩‍👧", "👨‍👩‍👧"]);
+  }
+
+  #[test]
+  fn test_split_code() {
+    assert_eq!(split_to_markup("see [code]one two three[/code]", 9), vec!["see [code]one[/code]", "[code]two three[/code]"]);
+    assert_eq!(split_to_markup("[code]abcdefgh[/code]", 3), vec!["[code]abc[/code]", "[code]def[/code]", "[code]gh[/code]"]);
+    assert_eq!(
+      split_to_markup("[codeblock]fn a() {\n  b();\n}[/codeblock]", 7),
+      vec!["[codeblock]fn a() [/codeblock]", "[codeblock]{[/codeblock]", "[codeblock]  b();[/codeblock]", "[codeblock]}[/codeblock]"]
+    );
+  }
+
+  #[test]
+  fn test_split_reopens_tags() {
+    assert_eq!(
+      split_to_markup("[b]bold [color=#ff0000]and red[/color] text[/b]", 8),
+      vec!["[b]bold [color=#ff0000]and[/color][/b]", "[b][color=#ff0000]red[/color] text[/b]"]
+    );
+  }
+
+  #[test]
+  fn test_split_keeps_link_href() {
+    assert_eq!(
+      split_to_markup("[url]example.com/abcdef ghi[/url]", 12),
+      vec!["[url=https://example.com/abcdef%20ghi]example.com/[/url]", "[url=https://example.com/abcdef%20ghi]abcdef ghi[/url]"]
+    );
+    assert_eq!(split_to_markup("[url]a.com[/url] [url]b.com[/url]", 6), vec!["[url]a.com[/url]", "[url]b.com[/url]"]);
+  }
+
+  #[test]
+  fn test_split_reopens_quotes() {
+    assert_eq!(split_to_markup("[quote=lua]one two[/quote] three", 5), vec!["[quote=lua]one[/quote]", "[quote=lua]two[/quote]", "three"]);
+  }
+
+  #[test]
+  fn test_split_drops_empty_tags() {
+    assert_eq!(split_to_markup("first [i]second[/i]", 6), vec!["first", "[i]second[/i]"]);
+    assert_eq!(split_to_markup("[i]first[/i] second", 6), vec!["[i]first[/i]", "second"]);
+  }
+
+  #[test]
+  fn test_split_keeps_escapes() {
+    assert_eq!(split_to_markup("abc\\[b]", 3), vec!["abc", "\\[b]"]);
+  }
+
+  #[test]
+  fn test_split_markup() {
+    let parts = parse("[b][i][color=#ff0000]one two three four[/color][/i][/b]");
+    let chunks = split_markup(&parts, 45);
+    assert_eq!(
+      chunks.iter().map(|chunk| to_markup(chunk)).collect::<Vec<_>>(),
+      vec!["[b][i][color=#ff0000]one two[/color][/i][/b]", "[b][i][color=#ff0000]three[/color][/i][/b]", "[b][i][color=#ff0000]four[/color][/i][/b]"]
+    );
+    // a cut link writes out its href, which counts too
+    for chunk in split_markup(&parse("[url]example.com/abcdefghij[/url] and more text"), 45) {
+      assert!(to_markup(&chunk).chars().count() <= 45);
+    }
+  }
+
+  #[test]
+  fn test_split_chunks_fit() {
+    let parts = parse("[b]some [i]long[/i] text\nwith [:3] emotes and [url]https://example.com[/url] links[/b] [code]and some code[/code]");
+    for chunk in split(&parts, 7) {
+      assert!(length(&chunk) <= 7);
+      assert_eq!(parse(&to_markup(&chunk)), chunk);
+    }
+    for chunk in split_markup(&parts, 40) {
+      assert!(to_markup(&chunk).chars().count() <= 40);
+      assert_eq!(parse(&to_markup(&chunk)), chunk);
+    }
+  }
+
+  /// normalizes markup and formats the result back to markup
+  fn normalize_markup(input: &str) -> String {
+    to_markup(&normalize(&parse(input)))
+  }
+
+  #[test]
+  fn test_normalize_redundant_styles() {
+    assert_eq!(normalize_markup("[b][b]x[/b]"), "[b]x[/b]");
+    assert_eq!(normalize_markup("x[/b][/color][/url]"), "x");
+  }
+
+  #[test]
+  fn test_normalize_empty_toggles() {
+    assert_eq!(normalize_markup("a[i][/i]b[color=#ff0000][url][/url][/color]"), "ab");
+    assert_eq!(normalize_markup("text [b]"), "text ");
+  }
+
+  #[test]
+  fn test_normalize_merges_text() {
+    assert_eq!(normalize(&parse("some \\text and \\[bold\\] \\")), vec![Part::Text("some text and [bold] ".to_string())]);
+    assert_eq!(normalize_markup("\\[b]still escaped\\\\"), "[b\\]still escaped\\\\");
+  }
+
+  #[test]
+  fn test_normalize_reopened_colors() {
+    assert_eq!(normalize_markup("[color=#FF0000]a[/color][color=#ff0000]b[/color]"), "[color=#ff0000]ab[/color]");
+    assert_eq!(normalize_markup("[b]a[/b][b]b[/b]"), "[b]ab[/b]");
+    assert_eq!(normalize_markup("[color=#ff0000]a[/color][color=#00ff00]b[/color]"), "[color=#ff0000]a[/color][color=#00ff00]b[/color]");
+  }
+
+  #[test]
+  fn test_normalize_keeps_separate_links() {
+    assert_eq!(normalize_markup("[url]a.com[/url][url]b.com[/url]"), "[url]a.com[/url][url]b.com[/url]");
+  }
+
+  #[test]
+  fn test_normalize_unnests_links() {
+    assert_eq!(normalize_markup("[url]a.com[url=b.com]b[/url][/url]"), "[url]a.com[/url][url=b.com]b[/url]");
+  }
+
+  #[test]
+  fn test_normalize_nested_quotes() {
+    assert_eq!(normalize_markup("[quote=a][quote][b]x[/quote]y[/quote]"), "[quote=a][quote][b]x[/b][/quote][b]y[/b][/quote]");
+  }
+
+  #[test]
+  fn test_normalize_keeps_quote_blocks() {
+    assert_eq!(normalize_markup("a[quote][/quote]b"), "a[quote][/quote]b");
+    assert_eq!(normalize_markup("[quote]a[/quote][quote]b[/quote]"), "[quote]a[/quote][quote]b[/quote]");
+    assert_eq!(normalize_markup("[b]x[quote]y[/quote][/b]"), "[b]x[/b][quote][b]y[/b][/quote]");
+  }
+
+  #[test]
+  fn test_normalize_reopened_links() {
+    assert_eq!(normalize_markup("[b][url]x[/b]y[/url]"), "[b][url=https://xy]x[/url][/b][url=https://xy]y[/url]");
+    assert_eq!(normalize_markup("[url]a.c[quote]om[/quote][/url]"), "[url=https://a.com]a.c[/url][quote][url=https://a.com]om[/url][/quote]");
+    // links that only render as text keep an href that renders as text too
+    assert_eq!(normalize_markup("[i][url]exa[/i]mple.com:99999[/url]"), "[i][url=example.com:99999]exa[/url][/i][url=example.com:99999]mple.com:99999[/url]");
+    assert_eq!(normalize_markup("[b][url]x.com[/url][/b]"), "[b][url]x.com[/url][/b]");
+  }
+
+  #[test]
+  fn test_normalize_keeps_empty_code() {
+    assert_eq!(normalize_markup("a[code][/code]b"), "a[code][/code]b");
+  }
+
+  #[test]
+  fn test_normalize_renders_the_same() {
+    let inputs = [
+      "this [b]text has [i]weird[/b] nest[/i]ing",
+      "make it [b]bold\n and [i]italics[/i][/b]\n",
+      "[url]this is a link: [url]https://example.com[/url][/url]",
+      "[url]a.com/[b]x[url=b.com]y[/b]z[/url]",
+      "[color=#ff0000]red [color=#00ff00]green[/color] red[/color] [:3]",
+      "unterminated [b]tag [i]here",
+      "a[quote][/quote]b[quote]x[/quote][quote][b]y[/quote]z",
+      "[b]a[quote=lua]b\n[quote]c[/b]d[/quote][/quote]e",
+      "[b][url]x[/b]y[/url]",
+      "[url]a.c[quote]om[/quote][/url]",
+      "[i][url]exa[/i]mple.com:99999[/url]",
+      "a[code][/code]b",
+    ];
+    for input in inputs {
+      let parts = parse(input);
+      assert_eq!(render(normalize(&parts), false), render(parts, false));
+    }
+  }
+
+  /// makes html comparable to the html of normalized parts, which drops empty
+  /// styles the renderer still writes as empty inline elements, and keeps
+  /// styles open that are closed and reopened right away, while code, blocks
+  /// and links are kept as they are
+  fn comparable(html: &str) -> String {
+    let mut html = html.to_string();
+    let mut from = 0;
+    while let Some(offset) = html[from..].find("></") {
+      let close = from + offset + 1;
+      let name_end = close + html[close..].find('>').unwrap();
+      let name = html[close + 2..name_end].to_string();
+      let open = html[..close].rfind('<').unwrap();
+      let empty = !matches!(name.as_str(), "code" | "pre" | "div" | "blockquote" | "cite")
+        && html[open + 1..].starts_with(&name)
+        && matches!(html.as_bytes()[open + 1 + name.len()], b' ' | b'>');
+      if empty {
+        html.replace_range(open..=name_end, "");
+        from = 0;
+      } else {
+        from = close;
+      }
+    }
+    for name in ["strong", "em", "u", "s", "sup", "sub", "small", "span"] {
+      let close = format!("</{name}>");
+      let mut from = 0;
+      while let Some(offset) = html[from..].find(&close) {
+        let end = from + offset + close.len();
+        // find the tag this closes, and the one that opens right after
+        let mut depth = 0;
+        let mut open = None;
+        for (index, _) in html[..end - close.len()].match_indices('<').collect::<Vec<_>>().into_iter().rev() {
+          let tag = &html[index..index + html[index..].find('>').unwrap() + 1];
+          if tag == close {
+            depth += 1;
+          } else if tag.starts_with(&format!("<{name}")) && matches!(tag.as_bytes()[name.len() + 1], b' ' | b'>') {
+            if depth == 0 {
+              open = Some(tag.to_string());
+              break;
+            }
+            depth -= 1;
+          }
+        }
+        match open {
+          Some(open) if html[end..].starts_with(&open) => html.replace_range(end - close.len()..end + open.len(), ""),
+          _ => from = end,
+        }
+      }
+    }
+    html.replace("<div></div>", "<div><br></div>")
+  }
+
+  proptest! {
+
+    #[test]
+    fn prop_normalize_is_canonical(input in "(\\[/?(b|i|url|quote)\\]|\\[url=a\\.com\\]|\\[/?color(=#ff0000|=#00FF00)?\\]|\\[:3\\]|[ab\\[\\]\\\\\n]){0,32}") {
+      let normalized = normalize(&parse(&input));
+      prop_assert_eq!(&normalize(&normalized), &normalized);
+      prop_assert_eq!(&parse(&to_markup(&normalized)), &normalized);
+      prop_assert_eq!(check(&normalized), Ok(()));
+    }
+
+    #[test]
+    fn prop_normalize_renders_the_same(input in "(\\[/?(b|i|url|quote|spoiler)\\]|\\[url=a\\.com\\]|\\[quote=x\\]|\\[/?color(=#ff0000|=#00FF00)?\\]|\\[code\\]a?\\[/code\\]|\\[:3\\]|[ab.\\[\\]\\\\\n]){0,32}") {
+      let parts = parse(&input);
+      prop_assert_eq!(comparable(&render(normalize(&parts), false)), comparable(&render(parts, false)));
+    }
+
+  }
+
+}